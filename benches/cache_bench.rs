@@ -0,0 +1,94 @@
+//! How loading, saving, and listing the session cache scale with the
+//! number of sessions, and how YAML (the cache's format) compares with
+//! JSON for it.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use remote_jupyter::{ConnectionCache, OutputFormat, SessionFilter, SortOrder, TableLayout};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tempfile::TempDir;
+
+const SIZES: [usize; 4] = [1, 10, 50, 200];
+
+/// A cache file with `n` disconnected sessions on different hosts.
+fn cache_yaml(n: usize) -> String {
+    (0..n).map(|i| {
+        let port = 8000 + i;
+        format!("node{i:03}:{port}:\n  host: node{i:03}\n  port: {port}\n  \
+                 link: http://localhost:{port}/lab?token=5e2f{i:044}\n  pid: null\n  \
+                 token: 5e2f{i:044}\n  tags:\n  - bench\n  note: session {i}\n")
+    }).collect()
+}
+
+/// Point rjy's data directory at `dir` and write a cache of `n` sessions there.
+fn write_cache(dir: &Path, n: usize) {
+    env::set_var("HOME", dir);
+    env::set_var("XDG_DATA_HOME", dir);
+    fs::create_dir_all(dir.join("rjy")).unwrap();
+    fs::write(dir.join("rjy/sessions"), cache_yaml(n)).unwrap();
+}
+
+fn load_cache() -> ConnectionCache {
+    let mut sessions = ConnectionCache::new();
+    sessions.load().unwrap();
+    sessions
+}
+
+fn bench_load_save(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let mut group = c.benchmark_group("cache");
+    for n in SIZES {
+        write_cache(dir.path(), n);
+        group.bench_with_input(BenchmarkId::new("load", n), &n, |b, _| b.iter(load_cache));
+        let sessions = load_cache();
+        group.bench_with_input(BenchmarkId::new("save", n), &n, |b, _| b.iter(|| sessions.save().unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_formats(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let mut group = c.benchmark_group("format");
+    for n in SIZES {
+        write_cache(dir.path(), n);
+        let sessions = load_cache();
+        let yaml = serde_yaml::to_string(&sessions).unwrap();
+        let json = serde_json::to_string(&sessions).unwrap();
+        group.bench_with_input(BenchmarkId::new("yaml-serialize", n), &n,
+                               |b, _| b.iter(|| serde_yaml::to_string(&sessions).unwrap()));
+        group.bench_with_input(BenchmarkId::new("json-serialize", n), &n,
+                               |b, _| b.iter(|| serde_json::to_string(&sessions).unwrap()));
+        group.bench_with_input(BenchmarkId::new("yaml-deserialize", n), &n,
+                               |b, _| b.iter(|| serde_yaml::from_str::<ConnectionCache>(&yaml).unwrap()));
+        group.bench_with_input(BenchmarkId::new("json-deserialize", n), &n,
+                               |b, _| b.iter(|| serde_json::from_str::<ConnectionCache>(&json).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_list(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let layout = TableLayout {
+        fields: ["key", "pid", "status", "auto", "link", "tags", "note"].map(String::from).to_vec(),
+        width: Some(120),
+        colour: false,
+        hyperlinks: false
+    };
+    let mut group = c.benchmark_group("list");
+    for n in SIZES {
+        write_cache(dir.path(), n);
+        let sessions = load_cache();
+        group.bench_with_input(BenchmarkId::new("table", n), &n, |b, _| b.iter(|| {
+            sessions.list(&mut io::sink(), &SessionFilter::default(), &SortOrder::default(),
+                          OutputFormat::Table, None, &layout).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_load_save, bench_formats, bench_list);
+criterion_main!(benches);
@@ -0,0 +1,6488 @@
+use anyhow::{anyhow,Result};
+use std::fs::{self, File, OpenOptions, set_permissions, Permissions};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::env;
+use std::collections::{BTreeMap, HashMap};
+use std::cmp::Ordering;
+use std::iter;
+use std::path::{Path, PathBuf};
+use serde_derive::{Serialize,Deserialize};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::process::Command;
+use std::process::Stdio;
+use std::os::unix::process::CommandExt;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::Pid;
+use url::Url;
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use prettytable::{Table, format};
+use std::os::unix::fs::PermissionsExt;
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+ 
+#[macro_use] extern crate prettytable;
+
+const CACHE: &str = "rjy/sessions";
+
+/// Every session key, one per line, for shell completion to read without
+/// running rjy.
+const COMPLETION_KEYS: &str = "rjy/completion_keys.txt";
+
+/// The log of changes made to sessions, next to the cache.
+const AUDIT_LOG: &str = "rjy/audit.log";
+
+/// Settings, under $XDG_CONFIG_HOME (~/.config).
+const CONFIG: &str = "rjy/config.toml";
+
+/// Where the cache lived before rjy followed the XDG spec, relative to $HOME.
+const OLD_CACHE: &str = ".remote_jupyter_sessions";
+
+const INFO: &str = "\
+Remote Jupyter: Manage Remote Jupyter Sessions with SSH Tunneling
+usage: rjy [--help] <subcommand>
+
+Note: it is *highly* recommended you put the remote servers 
+you interact with in ~/.ssh/config and use ssh-add. See the 
+README for more information.
+
+Some examples:
+
+  Create a new session via the Jupyter link an alias to the host:
+  $ rjy new http://localhost:8906/lab?token=5e2f[...]8467 ponderosa
+
+  If the link has the server's hostname, the host can be left out:
+  $ rjy new http://gpu-node-03:8888/lab?token=5e2f[...]8467
+
+  Or, from the port and token alone:
+  $ rjy new-raw ponderosa 8906 5e2f[...]8467
+
+  List all active sessions:
+  $ rjy list
+
+  Disconnect a session (don't specify key to pick from a list):
+  $ rjy dc <key> [--all]
+
+  Reconnect a session (don't specify key to reconnect all):
+  $ rjy rc <key>
+
+  Drop the cached sessions (and disconnect them; don't specify key to pick
+  from a list):
+  $ rjy drop <key> [--all]
+
+  Run a command with JUPYTER_URL, JUPYTER_TOKEN, and JUPYTER_PORT set:
+  $ rjy run <key> -- python script.py
+
+  Make the cached sessions match a sessions file (see README):
+  $ rjy apply sessions.yaml [--prune] [--dry-run]
+
+See 'rjy --help' or 'rjy <subcommand> --help'. Or, see the README at: 
+https://github.com/vsbuffalo/remote_jupyter/.
+
+Please submit bugs or feature requests to: 
+https://github.com/vsbuffalo/remote_jupyter/issues.
+";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionStatus {
+    Connected,
+    Disconnected
+}
+
+impl ConnectionStatus {
+    pub fn msg(&self) -> String {
+        match self {
+            ConnectionStatus::Connected => "connected".to_string(),
+            ConnectionStatus::Disconnected => "disconnected".to_string()
+        }
+    }
+
+    /// A mark for the status that reads without colour.
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Connected => "●",
+            ConnectionStatus::Disconnected => "○"
+        }
+    }
+}
+
+/// When to colour output.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorChoice {
+    /// When stdout is a terminal, NO_COLOR isn't set, and CLICOLOR isn't 0.
+    #[default]
+    Auto,
+    Always,
+    Never
+}
+
+impl ColorChoice {
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && env::var_os("CLICOLOR").is_none_or(|value| value != "0")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connection {
+    pub host: String,
+    pub port: u16,
+    pub link: String,
+    pub pid: Option<u32>,
+    /// None for servers using password auth or no auth at all.
+    #[serde(default, deserialize_with = "empty_as_none", skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// When the session was created (RFC 3339, UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// When the session was last opened with `rjy open` (RFC 3339, UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub link_format: LinkFormat,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    /// Whether `reconnect-loop` keeps reconnecting the session however often
+    /// it fails.
+    #[serde(default)]
+    pub persistent: bool,
+    /// Whether to show the SSH server's login banner when connecting.
+    #[serde(default)]
+    pub capture_banner: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Whether `rjy rc` without a key reconnects this session.
+    #[serde(default = "default_true")]
+    pub auto: bool,
+    #[serde(default)]
+    pub forward_agent: bool,
+    /// The path the server is mounted under, e.g. "/jupyter/".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// "http", or "https" for servers using TLS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    /// The directory the server runs in, on the remote host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_dir: Option<String>,
+    /// The remote host's fully-qualified name, if looked up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_fqdn: Option<String>,
+    /// The local directory the session was created from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// The link's whole query string, token included (e.g. "token=abc&reset"),
+    /// so the local URL opens what the link did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<String>,
+    /// The local address the tunnel listens on; localhost if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    /// A shell command `reconnect-loop` runs when the session goes down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_disconnect_cmd: Option<String>,
+    /// A shell command `reconnect-loop` runs when the session comes back up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_reconnect_cmd: Option<String>,
+    /// How long each connection of the tunnel lasts before it's killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_kill_after_secs: Option<u64>,
+    /// When the current tunnel is killed (RFC 3339, UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_kill_at: Option<String>,
+    /// The command `rjy new --remote-cmd` started the server with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_cmd: Option<String>,
+    /// The pid of the server started with --remote-cmd, on the remote host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_jupyter_pid: Option<u32>,
+    /// Whether the token is kept in the OS keychain rather than the cache,
+    /// which then has a placeholder in its place.
+    #[serde(default)]
+    pub token_in_keychain: bool,
+    /// Environment variables for the tunnel's ssh process, from --env and
+    /// --env-file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env_vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub connection_type: ConnectionType,
+    /// Whether the tunnel goes through an SSH master connection (ssh -M),
+    /// so reconnecting needn't authenticate again while the master is up.
+    #[serde(default)]
+    pub persist_ssh: bool,
+    /// The master connection's control socket, with --persist-ssh.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_socket: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_pid: Option<u32>,
+    /// The notebook `rjy open` opens, percent-encoded as in a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipynb_path: Option<String>,
+    /// A port on the remote host forwarded back to one here (ssh -R), as
+    /// (remote port, local port).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_forward: Option<(u16, u16)>
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Older caches store a missing token as an empty string.
+fn empty_as_none<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where D: serde::Deserializer<'de> {
+    let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value.filter(|v| !v.is_empty()))
+}
+
+/// How `rjy list` prints sessions.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// A table for the terminal.
+    #[default]
+    Table,
+    /// A table without colour, whatever --color says (e.g. for terminals
+    /// that show ANSI codes as text).
+    TableNoColor,
+    /// A GitHub-flavored Markdown table, e.g. for issues and docs.
+    Markdown,
+    /// A JSON array of sessions, for scripts (same as --json).
+    Json,
+    /// Tab-separated lines in a fixed format (--porcelain).
+    #[value(skip)]
+    Porcelain(PorcelainVersion),
+    /// Just the keys, one per line (--quiet).
+    #[value(skip)]
+    Keys,
+    /// A block of lines per session (--long).
+    #[value(skip)]
+    Long { show_tokens: bool }
+}
+
+/// Which sessions a command applies to, e.g. `rjy list`'s.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SessionFilter {
+    /// Only sessions with this tag.
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Only sessions on hosts whose name contains this (can be repeated).
+    #[arg(long = "host", value_name = "HOST")]
+    pub hosts: Vec<String>,
+    /// Only sessions whose tunnel is up.
+    #[arg(long, conflicts_with = "dead_only")]
+    pub alive_only: bool,
+    /// Only sessions whose tunnel is down.
+    #[arg(long)]
+    pub dead_only: bool,
+    /// Only sessions with this status (same as --alive-only or --dead-only).
+    #[arg(long, value_enum, conflicts_with_all = ["alive_only", "dead_only"])]
+    pub status: Option<ConnectionStatus>
+}
+
+impl SessionFilter {
+    pub fn matches(&self, conn: &Connection) -> bool {
+        if let Some(tag) = &self.tag {
+            if !conn.tags.contains(tag) {
+                return false;
+            }
+        }
+        if !self.hosts.is_empty() && !self.hosts.iter().any(|host| conn.host.contains(host.as_str())) {
+            return false;
+        }
+        let alive_only = self.alive_only || self.status == Some(ConnectionStatus::Connected);
+        let dead_only = self.dead_only || self.status == Some(ConnectionStatus::Disconnected);
+        match conn.is_alive() {
+            true => !dead_only,
+            false => !alive_only
+        }
+    }
+}
+
+/// The columns `rjy list --fields` can show, by name.
+const LIST_FIELDS: [&str; 13] = ["name", "key", "status", "pid", "auto", "age", "host", "port", "local-port",
+                                 "link", "tags", "note", "remote-host"];
+
+/// The columns `rjy list` shows by default (after "name", if any session
+/// has one).
+const DEFAULT_LIST_FIELDS: [&str; 7] = ["key", "pid", "status", "auto", "link", "tags", "note"];
+
+/// Check column names for `rjy list`, accepting `local_port` for
+/// `local-port` as `--format` writes it.
+fn parse_list_fields(fields: &[String]) -> Result<Vec<String>> {
+    fields.iter()
+        .map(|field| {
+            let name = field.trim().to_lowercase().replace('_', "-");
+            match LIST_FIELDS.contains(&name.as_str()) {
+                true => Ok(name),
+                false => Err(anyhow!("Unknown list field '{}'. Valid fields: {}.", field, LIST_FIELDS.join(", ")))
+            }
+        })
+        .collect()
+}
+
+/// The columns of `rjy list`'s table that give way, in turn, when it's too
+/// wide for the terminal, and how narrow each may get at that turn (never
+/// narrower than its title).
+const SHRINK_ORDER: [(&str, usize); 8] = [("note", 12), ("tags", 8), ("remote-host", 16), ("host", 12),
+                                          ("link", 34), ("note", 0), ("tags", 0), ("link", 16)];
+
+/// What `rjy list` shows as a table, how wide the table may be (`None` to
+/// never shorten anything, e.g. with --full or into a pipe), and whether
+/// its statuses are coloured and its links made clickable.
+pub struct TableLayout {
+    pub fields: Vec<String>,
+    pub width: Option<usize>,
+    pub colour: bool,
+    pub hyperlinks: bool
+}
+
+/// The width of the terminal stdout is, or `None` if it isn't one.
+fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let columns = |text: &str| text.parse().ok().filter(|&columns: &usize| columns > 0);
+    if let Some(columns) = env::var("COLUMNS").ok().and_then(|text| columns(&text)) {
+        return Some(columns);
+    }
+    let size = File::open("/dev/tty").ok()
+        .and_then(|tty| Command::new("stty").arg("size").stdin(tty).stderr(Stdio::null()).output().ok())
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string());
+    // stty prints "rows columns" (0 columns if the size isn't known).
+    Some(size.and_then(|size| columns(size.split_whitespace().nth(1)?)).unwrap_or(80))
+}
+
+/// Whether the terminal is one known to make OSC 8 hyperlinks clickable.
+fn supports_hyperlinks() -> bool {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    let vte_version = var("VTE_VERSION").parse::<u32>().unwrap_or(0);
+    matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm" | "vscode" | "ghostty")
+        || var("TERM") == "xterm-kitty"
+        || ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION"].iter().any(|name| env::var_os(name).is_some())
+        // gnome-terminal and other VTE terminals, since 0.50.
+        || vte_version >= 5000
+}
+
+/// A duration as its largest whole unit, e.g. "3d" or "40s".
+fn format_age(secs: i64) -> String {
+    match secs.max(0) {
+        secs if secs >= 86400 => format!("{}d", secs / 86400),
+        secs if secs >= 3600 => format!("{}h", secs / 3600),
+        secs if secs >= 60 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs)
+    }
+}
+
+/// What `rjy list --sort` orders sessions by.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortKey {
+    /// Host, then port.
+    #[default]
+    Host,
+    Port,
+    /// Connected sessions first.
+    Status,
+    /// Oldest first.
+    Created,
+    /// Least recently opened first.
+    LastUsed
+}
+
+/// How `rjy list` orders sessions.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SortOrder {
+    /// What to sort the sessions by (ties go by host, then port).
+    #[arg(long, value_enum, default_value_t = SortKey::Host)]
+    pub sort: SortKey,
+    /// Reverse the order.
+    #[arg(long)]
+    pub reverse: bool
+}
+
+/// Versions of `rjy list --porcelain`. A version's fields, their order,
+/// and their escaping never change; changes get a new version.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PorcelainVersion {
+    /// key, status, pid, host, port, local_port, local_url, tags, note.
+    V1
+}
+
+/// Escape a field of `--porcelain` output: backslashes, tabs, newlines, and
+/// carriage returns become \\, \t, \n, and \r.
+fn porcelain_field(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// The `--porcelain=v1` line for the session `conn` under `key`.
+fn porcelain_v1_line(key: &str, conn: &Connection) -> String {
+    let fields = [key.to_string(),
+                  conn.status().msg(),
+                  conn.get_pid().map_or(String::new(), |p| p.to_string()),
+                  conn.host.clone(),
+                  conn.link_port().unwrap_or(conn.port).to_string(),
+                  conn.port.to_string(),
+                  conn.local_url(),
+                  conn.tags.join(","),
+                  conn.note.clone().unwrap_or_default()];
+    let fields: Vec<String> = fields.iter().map(|field| porcelain_field(field)).collect();
+    fields.join("\t")
+}
+
+/// Make `text` safe to put in a Markdown table cell.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// How `rjy healthcheck` reports on sessions.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum HealthcheckFormat {
+    /// Prometheus text exposition format.
+    Prometheus,
+    /// A JSON object with each session's state and summary counts.
+    Json
+}
+
+/// What `rjy __complete` lists, for shell completion.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Candidates {
+    /// Session keys.
+    Keys,
+    /// The hosts sessions are on.
+    Hosts
+}
+
+/// One session in `rjy healthcheck --format json`.
+#[derive(Debug, Serialize)]
+pub struct SessionHealth {
+    pub key: String,
+    pub host: String,
+    pub port: u16,
+    pub alive: bool,
+    pub pid: Option<u32>
+}
+
+/// The JSON report from `rjy healthcheck --format json`.
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// "ok" if every session is connected, "degraded" otherwise.
+    pub status: String,
+    pub connected: usize,
+    pub disconnected: usize,
+    pub sessions: Vec<SessionHealth>
+}
+
+/// One session in `rjy list --json`. Fields are only ever added, and
+/// missing values are null rather than left out.
+#[derive(Debug, Serialize)]
+pub struct SessionListing {
+    pub key: String,
+    pub name: Option<String>,
+    pub host: String,
+    /// The server's port on the remote host, as in the link.
+    pub port: u16,
+    /// The port the tunnel listens on locally.
+    pub local_port: u16,
+    /// The tunnel's pid, or null if it's down.
+    pub pid: Option<u32>,
+    /// "connected" or "disconnected".
+    pub status: String,
+    pub link: String,
+    pub local_url: String,
+    pub created_at: Option<String>,
+    pub last_used: Option<String>,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    pub pinned: bool,
+    pub persistent: bool
+}
+
+impl SessionListing {
+    pub fn new(key: &str, conn: &Connection) -> SessionListing {
+        SessionListing {
+            key: key.to_string(),
+            name: conn.alias.clone(),
+            host: conn.host.clone(),
+            port: conn.link_port().unwrap_or(conn.port),
+            local_port: conn.port,
+            pid: conn.get_pid(),
+            status: conn.status().msg(),
+            link: conn.link.clone(),
+            local_url: conn.local_url(),
+            created_at: conn.created.clone(),
+            last_used: conn.last_used.clone(),
+            tags: conn.tags.clone(),
+            note: conn.note.clone(),
+            pinned: conn.pinned,
+            persistent: conn.persistent
+        }
+    }
+}
+
+/// Escape a Prometheus label value.
+fn prometheus_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// How a session reaches its server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionType {
+    /// Through an SSH tunnel to the remote host.
+    #[default]
+    Ssh,
+    /// Directly, e.g. a server on this machine or reachable over a VPN; the
+    /// session is just a bookmark of its link.
+    Local
+}
+
+/// Which Jupyter front end a link points to.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkFormat {
+    /// Detect from the link's path, or use the server's default page.
+    #[default]
+    Auto,
+    /// The classic Notebook interface (/tree).
+    Classic,
+    /// JupyterLab (/lab).
+    Lab,
+    /// A JupyterHub single-user server (/user/<name>/).
+    Hub
+}
+
+impl LinkFormat {
+    /// Guess the format from a link's path; `Auto` if there's nothing to go on.
+    pub fn detect(path: &str) -> LinkFormat {
+        let (base_url, page) = split_base_url(path);
+        if base_url.starts_with("/user/") {
+            LinkFormat::Hub
+        } else if page.starts_with("lab") {
+            LinkFormat::Lab
+        } else if page.starts_with("tree") || page.starts_with("notebooks") {
+            LinkFormat::Classic
+        } else {
+            LinkFormat::Auto
+        }
+    }
+
+    pub fn msg(&self) -> String {
+        match self {
+            LinkFormat::Auto => "auto".to_string(),
+            LinkFormat::Classic => "classic".to_string(),
+            LinkFormat::Lab => "lab".to_string(),
+            LinkFormat::Hub => "hub".to_string()
+        }
+    }
+}
+
+/// Options for a new session beyond its link and host, shared by `rjy new`
+/// and sessions files.
+#[derive(Args, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NewOptions {
+    /// Don't warn about links without a token (e.g. for servers using password auth).
+    #[arg(long)]
+    pub allow_empty_token: bool,
+    /// A free-form note describing the session.
+    #[arg(short = 'l', long, visible_alias = "label")]
+    #[serde(alias = "label")]
+    pub note: Option<String>,
+    /// Use the notebook path in the link (e.g. lab/tree/project/analysis.ipynb)
+    /// as the note, if no --note is given.
+    #[arg(long)]
+    pub label_from_url: bool,
+    /// Use the name of the current directory (e.g. the project's) as the
+    /// note, if no --note is given.
+    #[arg(long, conflicts_with = "label_from_url")]
+    pub label_from_dir: bool,
+    /// Use the remote host's fully-qualified name (from 'hostname -f' over
+    /// SSH) as the note, if no --note is given.
+    #[arg(long, conflicts_with_all = ["label_from_url", "label_from_dir"])]
+    pub label_from_remote_hostname: bool,
+    /// Use the host and the current time (e.g. "hpc01 @ 2024-01-15 14:30")
+    /// as the note, if no --note or other --label-from-* option is given.
+    #[arg(long)]
+    pub auto_label: bool,
+    /// Which Jupyter front end the link is for.
+    #[arg(long, value_enum, default_value_t = LinkFormat::Auto)]
+    pub link_format: LinkFormat,
+    /// Tag the session, to group it with others (can be repeated).
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+    /// A memorable name for the session, to use in place of its key.
+    #[arg(long = "name", value_name = "NAME", visible_aliases = ["alias", "nickname"])]
+    pub alias: Option<String>,
+    /// Don't reconnect this session when reconnecting all sessions.
+    #[arg(long)]
+    pub no_auto: bool,
+    /// Have 'rjy reconnect-loop' keep reconnecting this session, ignoring
+    /// --max-attempts.
+    #[arg(short = 'P', long)]
+    pub persistent: bool,
+    /// Forward the SSH agent (ssh -A), e.g. for git over SSH from the kernel.
+    #[arg(long)]
+    pub forward_agent: bool,
+    /// Show the SSH server's login banner (e.g. password expiry notices)
+    /// when connecting.
+    #[arg(long, overrides_with = "suppress_ssh_banner")]
+    pub ssh_banner: bool,
+    /// Don't show the SSH login banner (the default).
+    #[arg(long, overrides_with = "ssh_banner")]
+    #[serde(skip)]
+    pub suppress_ssh_banner: bool,
+    /// The directory the server runs in, for reference (taken from pasted
+    /// `jupyter server list` output if not given).
+    #[arg(long)]
+    pub root_dir: Option<String>,
+    /// The local address to bind the tunnel to, instead of localhost (e.g.
+    /// 0.0.0.0 to share the session with other machines).
+    #[arg(long)]
+    pub bind_address: Option<String>,
+    /// A shell command for 'rjy reconnect-loop' to run when the session goes
+    /// down, with RJYKEY, RJYHOST, and RJYPORT set.
+    #[arg(long = "on-disconnect-run", value_name = "COMMAND")]
+    pub on_disconnect_cmd: Option<String>,
+    /// A shell command for 'rjy reconnect-loop' to run when the session comes
+    /// back up, with the same variables set.
+    #[arg(long = "on-reconnect-run", value_name = "COMMAND")]
+    pub on_reconnect_cmd: Option<String>,
+    /// Kill the tunnel this many seconds after it's started (or reconnected),
+    /// e.g. for a finite-length experiment.
+    #[arg(long, value_name = "SECS")]
+    pub timeout_kill: Option<u64>,
+    /// Keep the token in the OS keychain (macOS Keychain, or the Secret
+    /// Service with secret-tool) instead of the session cache.
+    #[arg(long)]
+    pub keychain: bool,
+    /// Before starting the tunnel, check over SSH that the Jupyter server
+    /// answers on the remote host (catches a wrong port; takes a second or two).
+    #[arg(long)]
+    pub check_remote: bool,
+    /// The port the Jupyter server listens on, on the remote host, if not the
+    /// link's (e.g. for JupyterHub links, or a server behind a proxy).
+    #[arg(long, visible_alias = "tunnel-port")]
+    #[serde(alias = "tunnel_port")]
+    pub remote_port: Option<u16>,
+    /// Set an environment variable for the tunnel's ssh process, e.g.
+    /// KRB5CCNAME or SSH_AUTH_SOCK (can be repeated; overrides --env-file).
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    pub env: Vec<String>,
+    /// Read KEY=VALUE lines (and # comments) for the ssh process's
+    /// environment from a file. Only the values are kept, not the path.
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+    /// Don't start an SSH tunnel, as the server is reachable from here (e.g.
+    /// it runs on this machine, or over a VPN); just keep its link.
+    #[arg(long, conflicts_with_all = ["bind_address", "remote_port", "forward_agent", "check_remote",
+                                      "timeout_kill", "env", "env_file", "persist_ssh", "remote_forward"])]
+    pub local_only: bool,
+    /// Keep an SSH master connection (ssh -M) for the tunnel, so reconnecting
+    /// while it's up adds the tunnel back without authenticating again.
+    #[arg(long)]
+    pub persist_ssh: bool,
+    /// The notebook for 'rjy open' to open, rather than the Jupyter home,
+    /// relative to the server's root directory (e.g. project/analysis.ipynb).
+    #[arg(long, value_name = "REMOTE_PATH")]
+    pub ipynb_path: Option<String>,
+    /// Also forward a port on the remote host back to this machine (ssh -R),
+    /// e.g. 5432:5432 for the kernel to reach a local database, in the
+    /// tunnel's ssh process.
+    #[arg(long, value_name = "REMOTE_PORT:LOCAL_PORT", value_parser = parse_port_pair)]
+    pub remote_forward: Option<(u16, u16)>
+}
+
+impl NewOptions {
+    /// The environment variables --env-file and --env set, the latter
+    /// taking precedence.
+    pub fn env_vars(&self) -> Result<BTreeMap<String, String>> {
+        let mut vars = BTreeMap::new();
+        if let Some(path) = &self.env_file {
+            let contents = fs::read_to_string(path)
+                .map_err(|err| anyhow!("Failed to read env file '{:?}': {}", path, err))?;
+            for (i, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (key, value) = parse_env_var(line.strip_prefix("export ").unwrap_or(line))
+                    .map_err(|err| anyhow!("In env file '{:?}', line {}: {}", path, i + 1, err))?;
+                vars.insert(key, value);
+            }
+        }
+        for assignment in self.env.iter() {
+            let (key, value) = parse_env_var(assignment)
+                .map_err(|err| anyhow!("In --env '{}': {}", assignment, err))?;
+            vars.insert(key, value);
+        }
+        Ok(vars)
+    }
+}
+
+/// A `--remote-forward` spec, `REMOTE_PORT:LOCAL_PORT`.
+fn parse_port_pair(spec: &str) -> Result<(u16, u16)> {
+    let port = |port: &str| port.trim().parse::<u16>().ok().filter(|&port| port != 0);
+    match spec.split_once(':').map(|(remote, local)| (port(remote), port(local))) {
+        Some((Some(remote), Some(local))) => Ok((remote, local)),
+        _ => Err(anyhow!("expected REMOTE_PORT:LOCAL_PORT, e.g. 5432:5432"))
+    }
+}
+
+/// A `KEY=VALUE` assignment, with the value's surrounding quotes, if any,
+/// removed.
+fn parse_env_var(assignment: &str) -> Result<(String, String)> {
+    let Some((key, value)) = assignment.split_once('=') else {
+        return Err(anyhow!("expected KEY=VALUE"));
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("the variable name is empty"));
+    }
+    if key.contains(char::is_whitespace) {
+        return Err(anyhow!("the variable name '{}' contains whitespace", key));
+    }
+    let value = value.trim();
+    let value = ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote).and_then(|v| v.strip_suffix(*quote)))
+        .unwrap_or(value);
+    if value.contains('\n') || value.contains('\r') {
+        return Err(anyhow!("the value of {} contains a newline", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Whether to open a session in the browser once it's (re)connected.
+#[derive(Args, Debug, Clone, Default)]
+pub struct BrowserOptions {
+    /// Open the session in the browser once its tunnel is up.
+    #[arg(long)]
+    pub open: bool,
+    /// Don't open the session, even if the config file sets auto_open.
+    #[arg(long, conflicts_with = "open")]
+    pub no_open: bool
+}
+
+impl BrowserOptions {
+    pub fn wanted(&self) -> Result<bool> {
+        match (self.open, self.no_open) {
+            (true, _) => Ok(true),
+            (_, true) => Ok(false),
+            _ => Ok(Config::load()?.auto_open)
+        }
+    }
+}
+
+/// How `rjy merge` settles a key both caches have with different sessions.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum MergeStrategy {
+    /// Keep this machine's session.
+    #[default]
+    LocalWins,
+    /// Take the merged file's session.
+    RemoteWins,
+    /// Ask for each conflict.
+    Interactive
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeResolution {
+    KeepLocal,
+    KeepRemote,
+    Skip
+}
+
+/// A key both caches have, with different sessions.
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+    pub key: String,
+    pub local: Connection,
+    pub remote: Connection,
+    pub resolution: MergeResolution
+}
+
+impl MergeConflict {
+    /// Ask on the terminal which side to keep.
+    fn ask(&mut self) -> Result<()> {
+        if !io::stdin().is_terminal() {
+            return Err(anyhow!("Cannot ask how to merge session {} without a terminal; use \
+                               --strategy local-wins or remote-wins.", self.key));
+        }
+        println!("Session {} differs:", self.key);
+        for (side, conn) in [("local", &self.local), ("remote", &self.remote)] {
+            println!("  {:<7} {} (name: {}, tags: {}, note: {})", side, redact_link(&conn.link),
+                     conn.alias.as_deref().unwrap_or(""), conn.tags.join(","),
+                     conn.note.as_deref().unwrap_or(""));
+        }
+        loop {
+            print!("Keep [l]ocal, take [r]emote, or [s]kip? ");
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                return Err(anyhow!("No answer given for session {}.", self.key));
+            }
+            self.resolution = match answer.trim().to_lowercase().as_str() {
+                "l" | "local" => MergeResolution::KeepLocal,
+                "r" | "remote" => MergeResolution::KeepRemote,
+                "s" | "skip" => MergeResolution::Skip,
+                _ => continue
+            };
+            return Ok(());
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Switch {
+    #[value(alias = "true")]
+    On,
+    #[value(alias = "false")]
+    Off
+}
+
+/// How a session was created, kept so it can be recreated later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Origin {
+    /// The `rjy` command line, with the token replaced by `TOKEN_PLACEHOLDER`.
+    pub command: Vec<String>,
+    pub ssh_args: Vec<String>,
+    pub version: String
+}
+
+const TOKEN_PLACEHOLDER: &str = "<token>";
+
+/// How much of a session's note is shown in `rjy list`.
+const NOTE_WIDTH: usize = 30;
+
+impl Origin {
+    pub fn capture(link: &str, token: &str, ssh_args: Vec<String>) -> Self {
+        let mut command: Vec<String> = env::args().collect();
+        if let Some(program) = command.first_mut() {
+            *program = "rjy".to_string();
+        }
+        for arg in command.iter_mut() {
+            // Keep just the link from console output (or JSON) pasted in its
+            // place, or read from stdin.
+            if arg == "-" || find_links(arg).iter().any(|(found, _)| normalize_link(found) == link) {
+                *arg = link.to_string();
+            }
+            *arg = redact_token(arg, token);
+        }
+        Origin {
+            command,
+            ssh_args,
+            version: env!("CARGO_PKG_VERSION").to_string()
+        }
+    }
+}
+
+fn encode_token(token: &str) -> String {
+    url::form_urlencoded::byte_serialize(token.as_bytes()).collect()
+}
+
+/// What to percent-encode in a segment of a notebook path: everything but
+/// the characters RFC 3986 leaves unreserved.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Percent-encode a file path for a URL, keeping its slashes.
+fn encode_path(path: &str) -> String {
+    path.trim_matches('/')
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Replace the token in `text` with a placeholder, whether `text` is the
+/// token itself or has it as a `token=...` query parameter.
+fn redact_token(text: &str, token: &str) -> String {
+    if token.is_empty() {
+        return text.to_string();
+    }
+    if text == token {
+        return TOKEN_PLACEHOLDER.to_string();
+    }
+    let redacted = format!("token={}", TOKEN_PLACEHOLDER);
+    text.replace(&format!("token={}", token), &redacted)
+        .replace(&format!("token={}", encode_token(token)), &redacted)
+}
+
+/// The inverse of `redact_token`.
+fn unredact_token(text: &str, token: &str) -> String {
+    if text == TOKEN_PLACEHOLDER {
+        return token.to_string();
+    }
+    text.replace(&format!("token={}", TOKEN_PLACEHOLDER), &format!("token={}", encode_token(token)))
+}
+
+/// Hide the value of any `token=` in a link that may not parse, for error messages.
+fn redact_link(link: &str) -> String {
+    match link.find("token=") {
+        None => link.to_string(),
+        Some(start) => {
+            let value_start = start + "token=".len();
+            let value_end = link[value_start..]
+                .find(|c: char| c == '&' || c == '#' || c.is_whitespace())
+                .map_or(link.len(), |end| value_start + end);
+            format!("{}{}{}", &link[..value_start], TOKEN_PLACEHOLDER, &link[value_end..])
+        }
+    }
+}
+
+/// Quote an argument so it can be pasted into a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./:@=,+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+pub struct UrlParts {
+    scheme: String,
+    port: u16,
+    explicit_port: bool,
+    token: Option<String>,
+    path: String,
+    base_url: String,
+    /// The server's hostname, if the link (or one Jupyter printed alongside
+    /// it) has it rather than the remote host's own loopback.
+    remote_host: Option<String>,
+    query: Option<String>,
+    fragment: Option<String>
+}
+
+/// Local bind addresses that only accept connections from this machine.
+const LOOPBACK_ADDRESSES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// Bind addresses for every interface, which aren't themselves addresses
+/// to connect to.
+const WILDCARD_ADDRESSES: [&str; 4] = ["0.0.0.0", "*", "", "::"];
+
+/// Hostnames in a link that refer to the machine Jupyter runs on.
+const LOCAL_HOSTS: [&str; 4] = ["localhost", "127.0.0.1", "[::1]", "0.0.0.0"];
+
+/// The notebook or file path a Jupyter link opens, e.g.
+/// "my_project/analysis.ipynb" for /lab/tree/my_project/analysis.ipynb.
+fn extract_path_label(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    let (_, page) = split_base_url(parsed_url.path());
+    let path = ["lab/tree/", "notebooks/"].iter()
+        .find_map(|prefix| page.strip_prefix(prefix))?;
+    let label = percent_decode_str(path).decode_utf8().ok()?;
+    match label.trim_matches('/') {
+        "" => None,
+        label => Some(label.to_string())
+    }
+}
+
+/// A note for a session on `host` saying when it was created, e.g.
+/// "hpc01 @ 2024-01-15 14:30".
+fn auto_label(host: &str) -> String {
+    format!("{} @ {}", host, Local::now().format("%Y-%m-%d %H:%M"))
+}
+
+/// The session numbers (1 to `count`) picked in `answer`: numbers or
+/// ranges, e.g. "1 3" or "2-4", separated by spaces or commas, in the order
+/// first given.
+fn parse_picks(answer: &str, count: usize) -> Result<Vec<usize>> {
+    let mut picked: Vec<usize> = Vec::new();
+    for choice in answer.split(|c: char| c.is_whitespace() || c == ',').filter(|c| !c.is_empty()) {
+        let (first, last) = choice.split_once('-').unwrap_or((choice, choice));
+        let range = first.parse::<usize>().ok().zip(last.parse::<usize>().ok())
+            .filter(|(first, last)| 1 <= *first && first <= last && *last <= count)
+            .ok_or_else(|| anyhow!("'{}' isn't one of the sessions 1-{}.", choice, count))?;
+        for i in range.0..=range.1 {
+            if !picked.contains(&i) {
+                picked.push(i);
+            }
+        }
+    }
+    match picked.is_empty() {
+        true => Err(anyhow!("No session chosen.")),
+        false => Ok(picked)
+    }
+}
+
+/// Undo what copying a token out of an email or chat tends to do to it:
+/// percent-encoding left over from the link, and punctuation or quotes
+/// around it.
+fn clean_token(token: &str) -> String {
+    let token = percent_decode_str(token).decode_utf8_lossy();
+    token.trim_end_matches(['.', ',', ';', ':', '!', '"', '\'', ')', ']', '>'])
+        .trim_start_matches(['"', '\'', '(', '[', '<'])
+        .to_string()
+}
+
+/// Whether `token` looks like one Jupyter generates (48 hex digits by
+/// default). Custom tokens are allowed, so this only decides whether to warn.
+fn looks_like_token(token: &str) -> bool {
+    (32..=64).contains(&token.len()) && token.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Tidy a link before it's stored: write the host of a link for the remote
+/// host's own loopback (127.0.0.1, ::1, or 0.0.0.0) as `localhost`, which is
+/// where the tunnel makes it available locally, and clean up its token.
+fn normalize_link(link: &str) -> String {
+    let Ok(mut url) = Url::parse(link) else {
+        return link.to_string();
+    };
+    let mut changed = false;
+    if url.host_str().is_some_and(|host| host != "localhost" && LOCAL_HOSTS.contains(&host)) {
+        changed = url.set_host(Some("localhost")).is_ok();
+    }
+    let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if pairs.iter().any(|(key, value)| key == "token" && clean_token(value) != *value) {
+        url.query_pairs_mut().clear().extend_pairs(pairs.iter().map(|(key, value)| match key.as_str() {
+            "token" => (key.clone(), clean_token(value)),
+            _ => (key.clone(), value.clone())
+        }));
+        changed = true;
+    }
+    match changed {
+        true => url.to_string(),
+        false => link.to_string()
+    }
+}
+
+/// Remove ANSI escape sequences, which copying from `less -R` or a terminal
+/// multiplexer can bring along: colors and other CSI sequences, and OSC ones
+/// such as the OSC 8 hyperlinks some terminals wrap Jupyter's links in (their
+/// visible text, the link itself, is kept).
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters, then a final byte in @ to ~.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            },
+            // OSC: up to BEL or ST (ESC \).
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    plain
+}
+
+/// Undo what copying a link out of a terminal, email, or chat tends to do to
+/// it before it's parsed: escape sequences, quotes or angle brackets around
+/// it (as Outlook adds), and, when the text is clearly one link, the spaces
+/// and newlines of the terminal wrapping it. Anything else (e.g. console
+/// output) is left to `find_links`.
+fn clean_link(text: &str) -> String {
+    let text = strip_ansi(text);
+    let text = text.trim()
+        .trim_start_matches(['"', '\'', '`', '<'])
+        .trim_end_matches(['"', '\'', '`', '>'])
+        .trim();
+    let one_link = ["http://", "https://"].iter().any(|scheme| text.starts_with(scheme))
+        && text.matches("://").count() == 1
+        && !text.contains(" :: ");
+    match one_link {
+        true => text.chars().filter(|c| !c.is_whitespace()).collect(),
+        false => text.to_string()
+    }
+}
+
+/// Whether `line` continues a link the terminal wrapped onto it.
+fn is_wrapped_link(line: &str) -> bool {
+    let line = line.trim_end();
+    !line.is_empty() && !line.starts_with(char::is_whitespace) && !line.starts_with('[')
+        && !line.contains(char::is_whitespace) && !line.contains("://")
+}
+
+/// A Jupyter link found in pasted text, with the directory the server runs
+/// in if the text says (as `jupyter server list` does).
+pub struct FoundLink {
+    link: String,
+    parts: UrlParts,
+    root_dir: Option<String>
+}
+
+/// The links in `jupyter server list --json` output: one JSON object per
+/// server (or an array of them), with the token apart from the URL.
+fn find_json_links(text: &str) -> Vec<(String, Option<String>)> {
+    let values: Vec<serde_json::Value> = match serde_json::from_str(text) {
+        Ok(serde_json::Value::Array(values)) => values,
+        Ok(value) => vec![value],
+        Err(_) => text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+    };
+    values.iter()
+        .filter_map(|server| {
+            let mut url = Url::parse(server.get("url")?.as_str()?).ok()?;
+            if let Some(token) = server.get("token").and_then(|t| t.as_str()).filter(|t| !t.is_empty()) {
+                url.query_pairs_mut().append_pair("token", token);
+            }
+            let root_dir = ["root_dir", "notebook_dir"].iter()
+                .find_map(|field| server.get(field).and_then(|dir| dir.as_str()))
+                .map(|dir| dir.to_string());
+            Some((url.to_string(), root_dir))
+        })
+        .collect()
+}
+
+/// All http(s) links in `text`, e.g. the console output of `jupyter lab`,
+/// rejoining links the terminal wrapped across lines. A link followed by
+/// ` :: <dir>`, as in `jupyter server list` output, comes with that directory.
+fn find_links(text: &str) -> Vec<(String, Option<String>)> {
+    let json_links = find_json_links(text);
+    if !json_links.is_empty() {
+        return json_links;
+    }
+    let lines: Vec<&str> = text.lines().collect();
+    let mut links = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let mut rest = *line;
+        while let Some(start) = ["http://", "https://"].iter().filter_map(|scheme| rest.find(scheme)).min() {
+            rest = &rest[start..];
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let mut link = rest[..end].to_string();
+            if end == rest.len() {
+                link.extend(lines[i + 1..].iter()
+                            .take_while(|next| is_wrapped_link(next))
+                            .map(|next| next.trim_end()));
+            }
+            rest = &rest[end..];
+            let root_dir = rest.trim_start().strip_prefix("::")
+                .map(|dir| dir.trim().to_string())
+                .filter(|dir| !dir.is_empty());
+            if root_dir.is_some() {
+                rest = "";
+            }
+            links.push((clean_link(&link), root_dir));
+        }
+    }
+    links
+}
+
+/// Pick the Jupyter link out of `text`, which may be a link or several lines
+/// pasted from Jupyter's console output or `jupyter server list`. Of the
+/// links for one server, the localhost one with a token is preferred; if
+/// there are links for several servers, `port` picks one, or else the user
+/// is asked.
+fn find_link(text: &str, port: Option<u16>) -> Result<FoundLink> {
+    let text = &clean_link(text);
+    if !text.contains(char::is_whitespace) && !text.starts_with(['{', '[']) {
+        return Ok(FoundLink { link: text.to_string(), parts: UrlParts::parse(text)?, root_dir: None });
+    }
+    let mut candidates: Vec<FoundLink> = find_links(text).into_iter()
+        .filter_map(|(link, root_dir)| {
+            UrlParts::parse(&link).ok().map(|parts| FoundLink { link, parts, root_dir })
+        })
+        .filter(|found| port.is_none_or(|port| found.parts.port == port))
+        .collect();
+    let mut ports: Vec<u16> = Vec::new();
+    for found in candidates.iter() {
+        if !ports.contains(&found.parts.port) {
+            ports.push(found.parts.port);
+        }
+    }
+    let port = match ports.len() {
+        0 => {
+            let for_port = port.map_or(String::new(), |port| format!(" for port {}", port));
+            return Err(anyhow!("Could not find a Jupyter link{} in the given text.", for_port));
+        },
+        1 => ports[0],
+        _ => choose_server(&candidates, &ports)?
+    };
+    candidates.retain(|found| found.parts.port == port);
+    let remote_host = candidates.iter().find_map(|found| found.parts.remote_host.clone());
+    let root_dir = candidates.iter().find_map(|found| found.root_dir.clone());
+    candidates.sort_by_key(|found| (found.parts.remote_host.is_some(), found.parts.token.is_none()));
+    let mut found = candidates.swap_remove(0);
+    found.parts.remote_host = remote_host;
+    found.root_dir = root_dir;
+    Ok(found)
+}
+
+/// Ask the remote host which Jupyter servers it's running, as `jupyter
+/// server list --json` output (falling back on the classic Notebook's
+/// `jupyter notebook list`).
+fn detect_servers(host: &str, jupyter: &str) -> Result<String> {
+    let list = format!("{jupyter} server list --json 2>/dev/null || {jupyter} notebook list --json",
+                       jupyter = jupyter);
+    let output = ssh_command()
+        .arg(host)
+        .arg(list)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+    if !output.status.success() {
+        return Err(anyhow!("Could not list the Jupyter servers on {} (ssh exited with {}).\n\
+                           If Jupyter isn't on the remote PATH, say how to run it with --jupyter.",
+                           host, output.status));
+    }
+    let servers = String::from_utf8_lossy(&output.stdout).to_string();
+    if find_links(&servers).is_empty() {
+        return Err(anyhow!("No running Jupyter servers found on {}.", host));
+    }
+    Ok(servers)
+}
+
+/// How long to wait for a server started with --remote-cmd to print its link.
+const REMOTE_LAUNCH_WAIT_SECS: u64 = 60;
+
+/// Suggested when a token is given on the command line.
+const HISTORY_NOTE: &str = "Note: the token is now in your shell history. To keep it out, \
+                            pipe the link to 'rjy new - <host>', or use --link-file or --link-env.";
+
+/// The text `rjy new` finds the link in, and the host, if given: from the
+/// link argument, stdin (if it's '-'), a file, or an environment variable.
+/// With a file or variable, the one argument given is the host.
+fn read_link(link: Option<&str>, host: Option<&str>, link_file: Option<&PathBuf>,
+             link_env: Option<&str>, from_clipboard: bool) -> Result<(String, Option<String>)> {
+    let elsewhere = match (link_file, link_env) {
+        (Some(path), _) => Some(fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read the link from {:?}: {}", path, err))?),
+        (None, Some(var)) => Some(env::var(var)
+            .map_err(|_| anyhow!("The environment variable {} is not set.", var))?),
+        (None, None) if from_clipboard => Some(read_clipboard()?),
+        (None, None) => None
+    };
+    match (elsewhere, link, host) {
+        (Some(_), Some(_), Some(_)) => {
+            Err(anyhow!("Give the link either as an argument or with \
+                         --link-file/--link-env/--from-clipboard, not both."))
+        },
+        (Some(text), host, None) => Ok((text, host.map(|host| host.to_string()))),
+        (_, Some("-"), host) => {
+            if io::stdin().is_terminal() {
+                eprintln!("Paste the Jupyter link (or Jupyter's output), then press Ctrl-D:");
+            }
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok((text, host.map(|host| host.to_string())))
+        },
+        (_, Some(link), host) => {
+            if link.contains("token=") {
+                eprintln!("{}", HISTORY_NOTE);
+            }
+            Ok((link.to_string(), host.map(|host| host.to_string())))
+        },
+        (_, None, _) => Err(anyhow!("No link given: pass it as an argument, '-' to read it from \
+                                    stdin, --link-file, --link-env, or --from-clipboard."))
+    }
+}
+
+/// Read the clipboard's text with whichever of the platform's clipboard
+/// tools is installed.
+fn read_clipboard() -> Result<String> {
+    let mut tools: Vec<&[&str]> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(&["pbpaste"]);
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-paste", "--no-newline"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard", "-o"]);
+        tools.push(&["xsel", "--clipboard", "--output"]);
+    }
+    if tools.is_empty() {
+        return Err(anyhow!("There's no clipboard to read the link from (no display). Pass the \
+                            link as an argument, or '-' to read it from stdin."));
+    }
+    for tool in &tools {
+        let output = match Command::new(tool[0]).args(&tool[1..]).stdin(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(anyhow!("Failed to run {}: {}", tool[0], err))
+        };
+        if !output.status.success() {
+            return Err(anyhow!("Could not read the clipboard with {}: {}", tool[0],
+                               String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        let text = String::from_utf8_lossy(&output.stdout).to_string();
+        if text.trim().is_empty() {
+            return Err(anyhow!("The clipboard is empty."));
+        }
+        return Ok(text);
+    }
+    Err(anyhow!("No clipboard tool found; install one of: {}.",
+                tools.iter().map(|tool| tool[0]).collect::<Vec<&str>>().join(", ")))
+}
+
+/// Put `text` on the clipboard with whichever of the platform's clipboard
+/// tools is installed, or else, e.g. over SSH, by asking the terminal to with
+/// an OSC 52 escape sequence. Returns what was used.
+fn write_clipboard(text: &str) -> Result<String> {
+    let mut tools: Vec<&[&str]> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(&["pbcopy"]);
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-copy"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+        tools.push(&["xsel", "--clipboard", "--input"]);
+    }
+    for tool in &tools {
+        // xclip and wl-copy stay around to serve the clipboard, so don't
+        // wait on their output.
+        let mut child = match Command::new(tool[0]).args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn() {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(anyhow!("Failed to run {}: {}", tool[0], err))
+        };
+        child.stdin.take().expect("stdin is piped").write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("Could not copy to the clipboard with {} ({}).", tool[0], status));
+        }
+        return Ok(tool[0].to_string());
+    }
+    let mut tty = OpenOptions::new().write(true).open("/dev/tty")
+        .map_err(|_| anyhow!("No clipboard tool found, and no terminal to copy through with OSC 52."))?;
+    let osc52 = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    // tmux only passes the sequence on to the terminal if it's wrapped.
+    match env::var_os("TMUX") {
+        Some(_) => write!(tty, "\x1bPtmux;{}\x1b\\", osc52.replace('\x1b', "\x1b\x1b"))?,
+        None => write!(tty, "{}", osc52)?
+    }
+    Ok("OSC 52 (the terminal's clipboard)".to_string())
+}
+
+/// Standard base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('=')
+            }
+        }
+    }
+    encoded
+}
+
+/// `text` as a QR code for the terminal, light on dark so it reads on a
+/// dark background: in half-block characters, or in '#'s if `ascii`.
+fn render_qr(text: &str, ascii: bool) -> Result<String> {
+    let code = QrCode::new(text.as_bytes())?;
+    Ok(match ascii {
+        true => code.render::<char>()
+            .dark_color(' ')
+            .light_color('#')
+            .module_dimensions(2, 1)
+            .build(),
+        false => code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build()
+    })
+}
+
+/// Ask which of several servers' links to use, by port.
+fn choose_server(candidates: &[FoundLink], ports: &[u16]) -> Result<u16> {
+    let links: Vec<String> = ports.iter()
+        .filter_map(|port| candidates.iter().find(|found| found.parts.port == *port))
+        .map(|found| match &found.root_dir {
+            Some(dir) => format!("{} :: {}", redact_link(&found.link), dir),
+            None => redact_link(&found.link)
+        })
+        .collect();
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!("The text has links for several Jupyter servers:\n  {}\n\
+                            Pick one with --port <port>.", links.join("\n  ")));
+    }
+    println!("The text has links for several Jupyter servers:");
+    for (i, link) in links.iter().enumerate() {
+        println!("  {}) {}", i + 1, link);
+    }
+    print!("Which one? [1-{}] ", ports.len());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    answer.trim().parse::<usize>().ok()
+        .and_then(|choice| ports.get(choice.wrapping_sub(1)).copied())
+        .ok_or_else(|| anyhow!("No server chosen."))
+}
+
+impl UrlParts {
+    /// The scheme to use through the tunnel. A link without a port goes
+    /// through a proxy, which terminates TLS, so the server behind it is
+    /// reached over plain http.
+    pub fn local_scheme(&self) -> String {
+        match self.explicit_port {
+            true => self.scheme.clone(),
+            false => "http".to_string()
+        }
+    }
+
+    /// The port to forward: `remote_port` if given, otherwise the link's own.
+    /// A link without a port only gives the public port its scheme implies,
+    /// which needn't be where the server listens, so that isn't guessed.
+    pub fn tunnel_port(&self, remote_port: Option<u16>) -> Result<u16> {
+        match (remote_port, self.explicit_port) {
+            (Some(port), _) => Ok(port),
+            (None, true) => Ok(self.port),
+            (None, false) => Err(anyhow!(
+                "The link has no port, so it's for port {} (the {} default) on a public URL,\n\
+                 most likely served through a proxy such as JupyterHub's or a web server's.\n\
+                 That's the port the URL is served on, not necessarily the port the Jupyter\n\
+                 server listens on, on the remote host, which is the one rjy forwards.\n\
+                 Pass that port with --remote-port <port> (--remote-port {} if it really is the same).",
+                self.port, self.scheme, self.port))
+        }
+    }
+}
+
+/// The path segments Jupyter serves its pages and API under. Whatever comes
+/// before the first of them in a link is the server's base_url.
+const JUPYTER_PAGES: [&str; 9] = ["lab", "tree", "notebooks", "edit", "view", "files",
+                                  "terminals", "doc", "api"];
+
+/// Split a link's path into the server's base_url (e.g. "/jupyter/") and the
+/// page under it (e.g. "lab/tree/analysis.ipynb").
+fn split_base_url(path: &str) -> (String, String) {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let page_start = segments.iter()
+        .position(|s| JUPYTER_PAGES.contains(s))
+        .unwrap_or(segments.len());
+    let base_url = match page_start {
+        0 => "/".to_string(),
+        _ => format!("/{}/", segments[..page_start].join("/"))
+    };
+    (base_url, segments[page_start..].join("/"))
+}
+
+impl UrlParts {
+    pub fn parse(link: &str) -> Result<Self> {
+        // whitespace can split the token, hiding part of it from redaction,
+        // so it's left out of the link as shown.
+        let shown: String = link.chars().filter(|c| !c.is_whitespace()).collect();
+        let bad_link = |problem: &str| {
+            anyhow!("Incorrect Jupyter link format: {}.\n\
+                    Got:      {}\n\
+                    Expected: a link like http://localhost:8888/lab?token=<token>\n\
+                    Make sure the link starts with http:// (or https://), and quote it\n\
+                    so your shell doesn't interpret the '?' and '&' characters in it.",
+                    problem, redact_link(&shown))
+        };
+        if link.chars().any(char::is_whitespace) {
+            return Err(bad_link("the link contains whitespace"));
+        }
+        let parsed_url = Url::parse(link)
+            .map_err(|err| bad_link(&format!("cannot parse it as a URL ({})", err)))?;
+        if parsed_url.scheme() != "http" && parsed_url.scheme() != "https" {
+            return Err(bad_link("the link is not an http:// or https:// URL"));
+        }
+        // links through a proxy (e.g. JupyterHub's) may have no port, in
+        // which case it's the scheme's default: 443 for https, 80 for http.
+        let port = match parsed_url.port_or_known_default() {
+            Some(port) => port,
+            None => { 
+                return Err(bad_link("no port in URL, and none implied by its scheme"))
+            }
+        };
+
+        // get the token from the URL's parameters.
+        let mut token: Option<String> = None;
+        let query_pairs = parsed_url.query_pairs();
+        for (key, value) in query_pairs {
+            if key == "token" {
+                token = Some(value.to_string());
+                break;
+            }
+        }
+
+        let token = token.filter(|value| !value.is_empty());
+        let path = parsed_url.path().to_string();
+        let remote_host = parsed_url.host_str()
+            .filter(|host| !LOCAL_HOSTS.contains(host))
+            .map(|host| host.to_string());
+        Ok(UrlParts {
+            scheme: parsed_url.scheme().to_string(),
+            port,
+            explicit_port: parsed_url.port().is_some(),
+            token,
+            base_url: split_base_url(&path).0,
+            path,
+            remote_host,
+            query: parsed_url.query().filter(|query| !query.is_empty()).map(|query| query.to_string()),
+            fragment: parsed_url.fragment().filter(|fragment| !fragment.is_empty()).map(|fragment| fragment.to_string())
+        })
+    }
+
+    /// The host to SSH to: the one given, or else the link's hostname if it
+    /// isn't localhost (e.g. http://gpu-node-03:8888/...).
+    pub fn ssh_host(&self, host: Option<&str>) -> Result<String> {
+        let host = host.map(unbracket);
+        match (host, self.remote_host.as_deref().map(unbracket)) {
+            (Some(host), Some(link_host)) if host != link_host => {
+                eprintln!("Warning: the link is for host '{}', but using the given host '{}'.",
+                          link_host, host);
+                Ok(host.to_string())
+            },
+            (Some(host), _) => Ok(host.to_string()),
+            (None, Some(link_host)) => Ok(link_host.to_string()),
+            (None, None) => Err(anyhow!("The link is for localhost, so the remote host \
+                                        to SSH to must be given, e.g. rjy new <link> <host>."))
+        }
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`: how many characters must
+/// be inserted, deleted, or changed to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let changed = diagonal + usize::from(a_char != b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = changed.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Shorten `text` to at most `width` characters, marking the cut with '…'.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(width.saturating_sub(1)).collect();
+    short.push('…');
+    short
+}
+
+/// Shorten a link to at most `width` characters: first its token, to its
+/// first few characters (`token=abcd…`), then its path, keeping the scheme,
+/// host and port (`http://localhost:8888/…token=abcd…`), and then whatever
+/// doesn't fit.
+fn short_link(link: &str, width: usize) -> String {
+    let fits = |text: &str| text.chars().count() <= width;
+    if fits(link) {
+        return link.to_string();
+    }
+    let Some(start) = link.find("token=") else {
+        return truncate(link, width);
+    };
+    let value_start = start + "token=".len();
+    let value_end = link[value_start..]
+        .find(|c: char| c == '&' || c == '#' || c.is_whitespace())
+        .map_or(link.len(), |end| value_start + end);
+    let token = truncate(&link[value_start..value_end], 5);
+    let elided = format!("{}{}{}", &link[..value_start], token, &link[value_end..]);
+    if fits(&elided) {
+        return elided;
+    }
+    match Url::parse(link) {
+        Ok(url) => truncate(&format!("{}/…token={}", url.origin().ascii_serialization(), token), width),
+        Err(_) => truncate(&elided, width)
+    }
+}
+
+/// The fields `--format` templates can use, in the order they're listed.
+const SESSION_FIELDS: [&str; 12] = ["key", "name", "host", "port", "local_port", "status", "pid",
+                                    "link", "local_url", "created_at", "tags", "note"];
+
+enum TemplatePart {
+    Text(String),
+    Field(String)
+}
+
+/// A `--format` template: text with `{field}` placeholders. `{{` and `}}`
+/// are literal braces, and `\t` and `\n` a tab and a newline.
+pub struct Template {
+    parts: Vec<TemplatePart>
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '\\' => match chars.peek() {
+                    Some('t') => { chars.next(); text.push('\t'); },
+                    Some('n') => { chars.next(); text.push('\n'); },
+                    Some('\\') => { chars.next(); text.push('\\'); },
+                    _ => text.push('\\')
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow!("Unclosed '{{' in format {:?}.", template))
+                        }
+                    }
+                    let name = name.trim().to_string();
+                    if !SESSION_FIELDS.contains(&name.as_str()) {
+                        return Err(anyhow!("Unknown field {{{}}} in format. Valid fields: {}.",
+                                           name, SESSION_FIELDS.join(", ")));
+                    }
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(TemplatePart::Field(name));
+                },
+                '}' => return Err(anyhow!("Unmatched '}}' in format {:?}; write '}}}}' for a brace.", template)),
+                c => text.push(c)
+            }
+        }
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    fn render(&self, conn: &Connection) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.clone(),
+                TemplatePart::Field(name) => conn.field(name).unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+/// Build a Jupyter link from its parts, for when there's no URL to paste.
+fn raw_link(scheme: &str, port: u16, token: &str) -> Result<String> {
+    let mut url = Url::parse(&format!("{}://localhost:{}/", scheme, port))
+        .map_err(|err| anyhow!("Cannot build a Jupyter link with scheme '{}': {}", scheme, err))?;
+    url.query_pairs_mut().append_pair("token", token);
+    Ok(url.to_string())
+}
+
+/// Build a link from the host, port, and token given as options in place of
+/// one, saying which is missing.
+fn link_from_parts(host: Option<&str>, port: Option<u16>, token: Option<&str>,
+                   allow_empty_token: bool) -> Result<String> {
+    if host.is_none() {
+        return Err(anyhow!("No host given: pass the host to SSH to with --host <host>."));
+    }
+    let Some(port) = port else {
+        return Err(anyhow!("No port given: pass the port the Jupyter server listens on with --port <port>."));
+    };
+    match token {
+        Some(token) => raw_link("http", port, token),
+        None if allow_empty_token => Ok(format!("http://localhost:{}/", port)),
+        None => Err(anyhow!("No token given: pass the server's token with --token <token> (or \
+                            --allow-empty-token if it has none)."))
+    }
+}
+
+/// Put `token` in place of the link's token (or add it, if it has none).
+fn replace_token(link: &str, token: &str) -> Result<String> {
+    let mut url = Url::parse(link)?;
+    let param = format!("token={}", encode_token(token));
+    // edit the query as written, so the rest of it (e.g. a bare "reset") is kept.
+    let mut params: Vec<String> = url.query().unwrap_or("").split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| param.to_string())
+        .collect();
+    match params.iter_mut().find(|param| param.starts_with("token=")) {
+        Some(old) => *old = param,
+        None => params.push(param)
+    }
+    url.set_query(Some(&params.join("&")));
+    Ok(url.to_string())
+}
+
+/// How long to wait for an SSH login banner before going on.
+const BANNER_WAIT_SECS: u64 = 3;
+
+/// Pass on what ssh prints to stderr as it logs in (the server's login
+/// banner, e.g. password expiry notices), up to the first line about the
+/// forwarded port or a few seconds, whichever comes first. ssh ignores
+/// SIGPIPE, so it carries on when rjy stops reading.
+fn show_banner(stderr: impl Read + Send + 'static, port: u16) {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let port = port.to_string();
+    let deadline = Instant::now() + Duration::from_secs(BANNER_WAIT_SECS);
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(left) {
+            Ok(line) => {
+                eprintln!("{}", line);
+                if line.contains(&port) {
+                    break;
+                }
+            },
+            // ssh exited, or the time's up.
+            Err(_) => break
+        }
+    }
+}
+
+/// Whether there's a browser to open, i.e. $BROWSER is set or there's a
+/// graphical environment for the platform's opener.
+fn has_browser() -> bool {
+    cfg!(target_os = "macos")
+        || ["BROWSER", "DISPLAY", "WAYLAND_DISPLAY"].iter()
+            .any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// Open `url` in the browser given by $BROWSER, or else the platform's
+/// opener.
+fn open_in_browser(url: &str) -> Result<()> {
+    let (program, mut command) = match env::var("BROWSER").ok().filter(|browser| !browser.trim().is_empty()) {
+        Some(browser) => {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(format!("{} \"$1\"", browser)).arg("sh");
+            (browser, command)
+        },
+        None if cfg!(target_os = "macos") => ("open".to_string(), Command::new("open")),
+        None => ("xdg-open".to_string(), Command::new("xdg-open"))
+    };
+    let status = command.arg(url).status().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => anyhow!("Could not open a browser: {} is not installed. Set BROWSER \
+                                           to your browser's command.", program),
+        _ => anyhow!("Failed to run {}: {}", program, err)
+    })?;
+    if !status.success() {
+        return Err(anyhow!("Could not open a browser: {} exited with {}.", program, status));
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal; anything but yes is a no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Whether `pid` is still a running ssh. A successful kill() only says some
+/// process has the pid, which may have been reused since, so on Linux check
+/// what it is. Its parent can't be checked: tunnels outlive the rjy that
+/// started them.
+#[cfg(target_os = "linux")]
+fn is_pid_running(pid: Pid) -> bool {
+    let Ok(status) = fs::read_to_string(format!("/proc/{}/status", pid)) else {
+        return false;
+    };
+    let field = |name: &str| status.lines().find_map(|line| line.strip_prefix(name)).map(str::trim);
+    let is_ssh = field("Name:").is_some_and(|name| name == "ssh" || name == ssh_comm());
+    is_ssh && !field("State:").is_some_and(|state| state.starts_with('Z'))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_pid_running(pid: Pid) -> bool {
+    kill(pid, Some(Signal::SIGCHLD)).is_ok()
+}
+
+/// The ssh program to run: the config's ssh_binary, or ssh on the PATH.
+fn ssh_binary() -> &'static PathBuf {
+    static SSH_BINARY: OnceLock<PathBuf> = OnceLock::new();
+    SSH_BINARY.get_or_init(|| Config::load().ok()
+                           .and_then(|config| config.ssh_binary)
+                           .unwrap_or_else(|| PathBuf::from("ssh")))
+}
+
+fn ssh_command() -> Command {
+    Command::new(ssh_binary())
+}
+
+/// The file name of the ssh program, which its processes' command lines contain.
+fn ssh_name() -> String {
+    ssh_binary().file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "ssh".to_string())
+}
+
+/// The ssh program's name as the kernel keeps it, for /proc/<pid>/comm:
+/// only its first 15 bytes.
+fn ssh_comm() -> String {
+    ssh_name().chars().take(15).collect()
+}
+
+/// Whether a command line runs ssh (or the config's ssh_binary): whether
+/// that's its program, or the script its interpreter runs. Other programs
+/// merely mentioning ssh, like sshd or ssh-agent, don't count.
+fn runs_ssh(cmdline: &str) -> bool {
+    let ssh = ssh_name();
+    cmdline.split_whitespace().take(2)
+        .filter_map(|arg| Path::new(arg).file_name())
+        .any(|program| program == "ssh" || program.to_string_lossy() == ssh)
+}
+
+/// The command line of process `pid`, with its arguments separated by spaces.
+#[cfg(target_os = "linux")]
+fn pid_cmdline(pid: u32) -> Option<String> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = cmdline.split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect();
+    Some(args.join(" "))
+}
+
+/// The command line of process `pid`, with its arguments separated by spaces.
+#[cfg(not(target_os = "linux"))]
+fn pid_cmdline(pid: u32) -> Option<String> {
+    let output = Command::new("ps").args(["-p", &pid.to_string(), "-o", "command="]).output().ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => None
+    }
+}
+
+/// The service tokens are stored under in the OS keychain, with the
+/// session's key as the account.
+const KEYCHAIN_SERVICE: &str = "rjy";
+
+/// Run a keychain tool, feeding it `input` if given, and return its output.
+fn run_keychain_tool(args: &[&str], input: Option<&str>) -> Result<String> {
+    let mut child = Command::new(args[0]).args(&args[1..])
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => anyhow!("No keychain tool found ({} isn't installed).", args[0]),
+            _ => anyhow!("Failed to run {}: {}", args[0], err)
+        })?;
+    if let Some(input) = input {
+        child.stdin.take().expect("stdin is piped").write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string())
+}
+
+/// Store a session's token in the OS keychain, replacing any already there.
+fn keychain_store(key: &str, token: &str) -> Result<()> {
+    if cfg!(target_os = "macos") {
+        run_keychain_tool(&["security", "add-generic-password", "-U", "-s", KEYCHAIN_SERVICE,
+                            "-a", key, "-w", token], None)?;
+    } else {
+        let label = format!("rjy token for {}", key);
+        run_keychain_tool(&["secret-tool", "store", "--label", &label,
+                            "service", KEYCHAIN_SERVICE, "account", key], Some(token))?;
+    }
+    Ok(())
+}
+
+/// Look up a session's token in the OS keychain.
+fn keychain_lookup(key: &str) -> Result<String> {
+    let token = match cfg!(target_os = "macos") {
+        true => run_keychain_tool(&["security", "find-generic-password", "-s", KEYCHAIN_SERVICE,
+                                    "-a", key, "-w"], None)?,
+        false => run_keychain_tool(&["secret-tool", "lookup", "service", KEYCHAIN_SERVICE,
+                                     "account", key], None)?
+    };
+    match token.is_empty() {
+        true => Err(anyhow!("The keychain has no token for {}.", key)),
+        false => Ok(token)
+    }
+}
+
+/// Remove a session's token from the OS keychain.
+fn keychain_delete(key: &str) -> Result<()> {
+    match cfg!(target_os = "macos") {
+        true => run_keychain_tool(&["security", "delete-generic-password", "-s", KEYCHAIN_SERVICE,
+                                    "-a", key], None)?,
+        false => run_keychain_tool(&["secret-tool", "clear", "service", KEYCHAIN_SERVICE,
+                                     "account", key], None)?
+    };
+    Ok(())
+}
+
+/// Pids already warned about by `is_ssh_pid_running`, so it only says so once.
+static REUSED_PIDS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn format_key(conn: &Connection) -> String {
+    make_key(&conn.host, conn.port)
+}
+
+/// A session's key, `host:port`, with IPv6 addresses in brackets so the
+/// port stays unambiguous (e.g. `[fd00::12]:8888`).
+fn make_key(host: &str, port: u16) -> String {
+    format!("{}:{}", bracket(unbracket(host)), port)
+}
+
+/// The key `s` stands for, if it's a key written another way: an IPv6
+/// address with or without brackets (`fd00::12:8888`).
+fn canonical_key(s: &str) -> Option<String> {
+    let (host, port) = s.rsplit_once(':')?;
+    Some(make_key(host, port.parse().ok()?))
+}
+
+/// An IPv6 address without the brackets it's written in in URLs.
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host)
+}
+
+/// A host as written before a `:port`, with an IPv6 address in brackets.
+fn bracket(host: &str) -> String {
+    match host.contains(':') {
+        true => format!("[{}]", host),
+        false => host.to_string()
+    }
+}
+
+impl Connection {
+    pub fn new(link: &str, host: &str, options: &NewOptions) -> Result<Connection> {
+        let mut conn = Connection::from_link(link, host, options)?;
+        if options.check_remote {
+            let api_url = format!("{}://localhost:{}{}api", conn.scheme(), conn.port, conn.base_url());
+            let ssh_opts = ["-o".to_string(), "ConnectTimeout=10".to_string()];
+            if !Connection::check_remote_accessible(&conn.host, &api_url, &ssh_opts)? {
+                return Err(anyhow!("No Jupyter server answered at {} on {}.\n\
+                                   Check the port in the link (or --remote-port).", api_url, conn.host));
+            }
+        }
+        // Initiate the connection and return the struct.
+        if !conn.is_local() {
+            conn.reconnect()?;
+        }
+        Ok(conn)
+    }
+
+    /// The session for `link` on `host`, as `new` makes it, but without
+    /// checking the server or starting the tunnel.
+    fn from_link(link: &str, host: &str, options: &NewOptions) -> Result<Connection> {
+        let raw_token = UrlParts::parse(link)?.token;
+        let link = &normalize_link(link);
+        let url_parts = UrlParts::parse(link)?;
+        match &url_parts.token {
+            None if !options.allow_empty_token => {
+                eprintln!("Note: the link has no token, so the server must be using password \
+                          authentication (or none).");
+            },
+            Some(token) if !looks_like_token(token) => {
+                eprintln!("Warning: the token doesn't look like one Jupyter generates (hex digits).\n\
+                          If the session gets 403 errors, compare it with the server's link \
+                          using 'rjy info <key> --show-token'.");
+            },
+            _ => {}
+        }
+        if let Some(address) = options.bind_address.as_deref().map(unbracket).filter(|a| !LOOPBACK_ADDRESSES.contains(a)) {
+            eprintln!("WARNING: binding the tunnel to {} lets other machines connect to this Jupyter \
+                      server.\nAnyone who can reach this machine on the port and has the token can \
+                      run code as you on the remote host;\nthe token is the only authentication.",
+                      address);
+        }
+        if raw_token != url_parts.token {
+            eprintln!("Note: removed percent-encoding or punctuation around the link's token.");
+        }
+        let link_format = match options.link_format {
+            LinkFormat::Auto => LinkFormat::detect(&url_parts.path),
+            format => format
+        };
+        let scheme = url_parts.local_scheme();
+        let working_dir = env::current_dir().ok();
+        let remote_fqdn = match options.label_from_remote_hostname {
+            true => Connection::get_remote_hostname(host)
+                .map_err(|err| eprintln!("Warning: {}; leaving the session unlabeled.", err))
+                .ok(),
+            false => None
+        };
+        let mut conn = Connection { 
+            host: unbracket(host).to_string(),
+            port: url_parts.tunnel_port(options.remote_port)?,
+            link: link.to_string(),
+            pid: None,
+            origin: None,
+            created: Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+            last_used: None,
+            token: url_parts.token,
+            note: match (&options.note, options.label_from_url, options.label_from_dir) {
+                (None, true, _) => extract_path_label(link),
+                (None, _, true) => working_dir.as_ref()
+                    .and_then(|dir| dir.file_name())
+                    .map(|name| name.to_string_lossy().to_string()),
+                (None, _, _) if remote_fqdn.is_some() => remote_fqdn.clone(),
+                (None, _, _) if options.auto_label => Some(auto_label(unbracket(host))),
+                (note, _, _) => note.clone()
+            },
+            link_format,
+            tags: options.tags.clone(),
+            pinned: false,
+            persistent: options.persistent,
+            capture_banner: options.ssh_banner && !options.suppress_ssh_banner,
+            alias: options.alias.clone(),
+            auto: !options.no_auto,
+            forward_agent: options.forward_agent,
+            scheme: Some(scheme),
+            base_url: Some(url_parts.base_url),
+            root_dir: options.root_dir.clone(),
+            remote_fqdn,
+            working_dir,
+            query: url_parts.query,
+            fragment: url_parts.fragment,
+            bind_address: options.bind_address.clone(),
+            on_disconnect_cmd: options.on_disconnect_cmd.clone(),
+            on_reconnect_cmd: options.on_reconnect_cmd.clone(),
+            auto_kill_after_secs: options.timeout_kill,
+            auto_kill_at: None,
+            remote_cmd: None,
+            remote_jupyter_pid: None,
+            token_in_keychain: false,
+            env_vars: options.env_vars()?,
+            connection_type: match options.local_only {
+                true => ConnectionType::Local,
+                false => ConnectionType::Ssh
+            },
+            persist_ssh: options.persist_ssh,
+            control_socket: None,
+            master_pid: None,
+            ipynb_path: options.ipynb_path.as_deref().map(encode_path),
+            remote_forward: options.remote_forward
+        };
+        conn.origin = Some(Origin::capture(&conn.link, conn.token_str(), conn.ssh_args()));
+        Ok(conn)
+    }
+    
+    pub fn get_pid(&self) -> Option<u32> {
+        match self.status() {
+            ConnectionStatus::Disconnected => None,
+            ConnectionStatus::Connected => {
+                self.pid
+            }
+        }
+    }
+
+    /// The command line of the session's tunnel process, if it has one.
+    pub fn pid_cmdline(&self) -> Option<String> {
+        pid_cmdline(self.pid?)
+    }
+
+    /// Whether the session's pid is running and is still ssh, rather than
+    /// some other process that was given the pid after the tunnel died.
+    pub fn is_ssh_pid_running(&self) -> bool {
+        let Some(pid) = self.pid else {
+            return false;
+        };
+        if !is_pid_running(Pid::from_raw(pid as i32)) {
+            return false;
+        }
+        match self.pid_cmdline() {
+            Some(cmdline) if !runs_ssh(&cmdline) => {
+                let mut warned = REUSED_PIDS.lock().unwrap_or_else(|err| err.into_inner());
+                if !warned.contains(&pid) {
+                    warned.push(pid);
+                    eprintln!("Warning: the tunnel of session {} is gone, and its pid {} now belongs \
+                              to another process ({}).", format_key(self), pid, truncate(&cmdline, 60));
+                }
+                false
+            },
+            // can't tell (e.g. ps isn't available), so go by the pid alone.
+            _ => true
+        }
+    }
+
+    /// Whether the session is up, as every view of it reports: its tunnel
+    /// (or, for a local session, its server) is running and hasn't outlived
+    /// its --timeout-kill.
+    pub fn status(&self) -> ConnectionStatus {
+        match self.tunnel_running() && !self.expired() {
+            true => ConnectionStatus::Connected,
+            false => ConnectionStatus::Disconnected
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        matches!(self.status(), ConnectionStatus::Connected)
+    }
+
+    /// Whether the tunnel process (or, for a local session, the server) is
+    /// running, even past the session's --timeout-kill.
+    pub fn tunnel_running(&self) -> bool {
+        match self.connection_type {
+            ConnectionType::Ssh => self.is_ssh_pid_running(),
+            ConnectionType::Local => self.jupyter_responsive()
+        }
+    }
+
+    /// Whether the session is a --local-only one, with no tunnel.
+    pub fn is_local(&self) -> bool {
+        self.connection_type == ConnectionType::Local
+    }
+
+    /// Whether the Jupyter server answers at the session's URL (with any
+    /// status, e.g. 403), for a local session, which has no tunnel process
+    /// to check.
+    pub fn jupyter_responsive(&self) -> bool {
+        // ureq is built without TLS, so settle for the port being open.
+        if self.scheme() == "https" {
+            return self.is_listening(unbracket(&self.local_host()));
+        }
+        match ureq::get(&format!("{}/api", self.server_url())).timeout(Duration::from_secs(1)).call() {
+            Ok(_) | Err(ureq::Error::Status(_, _)) => true,
+            Err(_) => false
+        }
+    }
+
+    /// Whether the tunnel has outlived its --timeout-kill.
+    pub fn expired(&self) -> bool {
+        self.auto_kill_at.as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|at| Utc::now() > at)
+    }
+
+    /// Warn about a tunnel that has outlived its --timeout-kill but is still
+    /// running (e.g. the process that was to kill it was itself killed).
+    pub fn warn_if_overdue(&self) {
+        if self.expired() && self.tunnel_running() {
+            eprintln!("Warning: session {} was due to be killed at {}, but its tunnel is still \
+                      running.\nUse 'rjy dc {}' to close it.",
+                      self.key(), self.auto_kill_at.as_deref().unwrap_or(""), self.key());
+        }
+    }
+
+    /// Kill the tunnel once its --timeout-kill is up, from a process that
+    /// outlives this one.
+    fn schedule_kill(&self, pid: u32, secs: u64) -> Result<()> {
+        // check it's still ssh, by program name, in case the tunnel exited and
+        // its pid was reused.
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("sleep \"$1\"; comm=$(cat /proc/\"$2\"/comm 2>/dev/null || ps -p \"$2\" -o comm=); \
+                  case ${comm##*/} in ssh|\"$3\"|\"$4\") kill \"$2\";; esac")
+            .arg("rjy-timeout-kill")
+            .arg(secs.to_string())
+            .arg(pid.to_string())
+            .arg(ssh_name())
+            .arg(ssh_comm())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| anyhow!("Failed to schedule the tunnel's --timeout-kill: {}", err))?;
+        // reap it, should this process still be around (e.g. reconnect-loop).
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec!["-Y".to_string(), "-N".to_string()];
+        if self.forward_agent {
+            args.push("-A".to_string());
+        }
+        args.push("-L".to_string());
+        args.push(self.forward_spec());
+        if let Some((remote, local)) = self.remote_forward {
+            args.push("-R".to_string());
+            args.push(format!("{}:localhost:{}", remote, local));
+        }
+        args.push(self.host.clone());
+        args
+    }
+
+    /// The tunnel's -L forwarding spec.
+    fn forward_spec(&self) -> String {
+        format!("{bind}:{port}:localhost:{port}", bind = bracket(self.bind_address()), port = self.port)
+    }
+
+    /// Where the session's --persist-ssh master connection keeps its control
+    /// socket, ~/.ssh/rjy_ctrl_<host>_<port>: one per session, so closing a
+    /// session never closes another's master.
+    pub fn control_socket_path(&self) -> Result<PathBuf> {
+        let name = format!("rjy_ctrl_{}_{}", self.host.replace('/', "_"), self.port);
+        Ok(PathBuf::from(env::var("HOME")?).join(".ssh").join(name))
+    }
+
+    /// Whether the session's master connection is up and answering on its
+    /// control socket (ssh -O check).
+    pub fn has_master_socket(&self) -> bool {
+        let Some(socket) = self.control_socket.as_ref().filter(|socket| socket.exists()) else {
+            return false;
+        };
+        ssh_command()
+            .arg("-S").arg(socket)
+            .args(["-O", "check", &self.host])
+            .envs(&self.env_vars)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Start the tunnel through the session's master connection, starting
+    /// the master first if it isn't up, and return the master's pid (the
+    /// process that carries the tunnel).
+    fn connect_through_master(&mut self) -> Result<u32> {
+        let socket = self.control_socket_path()?;
+        if self.control_socket.as_ref() != Some(&socket) || !self.has_master_socket() {
+            if let Some(dir) = socket.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            // a socket left by a master that died would stop a new one binding.
+            let _ = fs::remove_file(&socket);
+            self.control_socket = Some(socket.clone());
+            let mut args = vec!["-M".to_string(), "-S".to_string(), socket.display().to_string()];
+            args.extend(self.ssh_args());
+            self.master_pid = Some(self.spawn_ssh(args)?);
+            let started = Instant::now();
+            while !self.has_master_socket() {
+                self.reap_pid(self.master_pid);
+                if !self.master_pid.is_some_and(|pid| is_pid_running(Pid::from_raw(pid as i32)))
+                    || started.elapsed() > Duration::from_secs(MASTER_WAIT_SECS) {
+                    return Err(anyhow!("The SSH master connection to {} didn't come up (try 'ssh {}' \
+                                       to see why).", self.host, self.host));
+                }
+                thread::sleep(Duration::from_millis(250));
+            }
+        } else if !self.is_listening(unbracket(&self.local_host())) {
+            let output = ssh_command()
+                .arg("-S").arg(&socket)
+                .args(["-O", "forward", "-L", &self.forward_spec(), &self.host])
+                .envs(&self.env_vars)
+                .stdout(Stdio::null())
+                .output()
+                .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+            if !output.status.success() {
+                return Err(anyhow!("Could not add the tunnel to the SSH master connection to {}: {}",
+                                   self.host, String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            println!("Reused the SSH master connection to {} (no new login).", self.host);
+        }
+        self.master_pid.ok_or_else(|| anyhow!("The SSH master connection to {} has no pid.", self.host))
+    }
+
+    /// The port in the session's link, which (e.g. behind a proxy) needn't
+    /// be the port that's forwarded.
+    pub fn link_port(&self) -> Option<u16> {
+        Url::parse(&self.link).ok().and_then(|url| url.port_or_known_default())
+    }
+
+    /// The local address the tunnel listens on.
+    pub fn bind_address(&self) -> &str {
+        self.bind_address.as_deref().map_or("localhost", unbracket)
+    }
+
+    /// The local address to reach the tunnel at (or a local session's
+    /// server at), as written in a URL.
+    pub fn local_host(&self) -> String {
+        if self.is_local() {
+            return bracket(&self.host);
+        }
+        match self.bind_address() {
+            address if WILDCARD_ADDRESSES.contains(&address) => "localhost".to_string(),
+            address => bracket(address)
+        }
+    }
+
+    /// The local URL as other machines reach it, with this machine's
+    /// hostname if the tunnel listens on all addresses.
+    pub fn network_url(&self) -> Result<Url> {
+        let host = match self.bind_address() {
+            address if WILDCARD_ADDRESSES.contains(&address) => nix::unistd::gethostname()?
+                .to_string_lossy()
+                .to_string(),
+            _ => self.local_host()
+        };
+        let mut url = Url::parse(&self.local_url())?;
+        url.set_host(Some(&host))?;
+        Ok(url)
+    }
+
+    pub fn new_connection(&self) -> Result<u32> {
+        self.spawn_ssh(self.ssh_args())
+    }
+
+    /// Start ssh with `args` in the background, returning its pid.
+    fn spawn_ssh(&self, args: Vec<String>) -> Result<u32> {
+        if self.forward_agent && env::var_os("SSH_AUTH_SOCK").is_none() {
+            eprintln!("Warning: SSH_AUTH_SOCK is not set, so there is no SSH agent to forward.");
+        }
+        let mut child = ssh_command()
+            .args(args)
+            .envs(&self.env_vars)
+            .stdout(Stdio::null())
+            .stderr(if self.capture_banner { Stdio::piped() } else { Stdio::null() })
+            .spawn()?;
+        if let Some(stderr) = child.stderr.take() {
+            show_banner(stderr, self.port);
+        }
+        Ok(child.id())
+    }
+
+    /// Whether the Jupyter API at `api_url` answers with 200 on `host`,
+    /// checked by running curl there over SSH.
+    pub fn check_remote_accessible(host: &str, api_url: &str, ssh_opts: &[String]) -> Result<bool> {
+        let curl = format!("curl -sk -o /dev/null -w '%{{http_code}}' {}", shell_quote(api_url));
+        let output = ssh_command()
+            .args(ssh_opts)
+            .arg(host)
+            .arg(curl)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+        let code = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        match output.status.code() {
+            Some(255) => Err(anyhow!("Could not check the server: ssh to {} failed.", host)),
+            Some(127) => Err(anyhow!("Could not check the server: curl isn't installed on {}.", host)),
+            _ => Ok(code == "200")
+        }
+    }
+
+    /// The fully-qualified name `host` knows itself by, e.g. for an SSH
+    /// alias like gpu01.
+    pub fn get_remote_hostname(host: &str) -> Result<String> {
+        let output = ssh_command()
+            .args(["-o", "ConnectTimeout=10", host, "hostname -f 2>/dev/null || hostname"])
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        match (output.status.success(), name.is_empty()) {
+            (true, false) => Ok(name),
+            _ => Err(anyhow!("could not get the hostname of {} (ssh exited with {})", host, output.status))
+        }
+    }
+
+    /// Start a Jupyter server on `host` with `cmd`, in the background so it
+    /// outlives the SSH connection, and wait for it to print its link.
+    /// Returns the link and the server's pid on the remote host.
+    pub fn launch_and_capture_url(host: &str, cmd: &str) -> Result<(String, u32)> {
+        // setsid, where there is one, makes the pid a process group that
+        // kill-remote can end along with the server's children.
+        let script = format!("\
+            log=$(mktemp \"${{TMPDIR:-/tmp}}/rjy-jupyter.XXXXXX\") || exit 1
+            run=; command -v setsid >/dev/null 2>&1 && run=setsid
+            nohup $run sh -c {cmd} >\"$log\" 2>&1 </dev/null &
+            pid=$!
+            echo \"rjy-log $log\"
+            echo \"rjy-pid $pid\"
+            i=0
+            while [ $i -lt {wait} ] && kill -0 $pid 2>/dev/null && ! grep -q 'https*://[^ ]*:[0-9]' \"$log\"; do
+                sleep 1; i=$((i+1))
+            done
+            sleep 1
+            kill -0 $pid 2>/dev/null || echo rjy-exited
+            cat \"$log\"", cmd = shell_quote(cmd), wait = REMOTE_LAUNCH_WAIT_SECS);
+        eprintln!("Starting the server on {} (waiting up to {}s for its link)...", host, REMOTE_LAUNCH_WAIT_SECS);
+        let output = ssh_command()
+            .arg(host)
+            .arg(script)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+        if !output.status.success() {
+            return Err(anyhow!("Could not start the server on {} (ssh exited with {}).", host, output.status));
+        }
+        let text = String::from_utf8_lossy(&output.stdout).to_string();
+        let mut lines = text.lines();
+        let log = lines.next().and_then(|line| line.strip_prefix("rjy-log ")).unwrap_or("").to_string();
+        let pid = lines.next().and_then(|line| line.strip_prefix("rjy-pid "))
+            .and_then(|pid| pid.trim().parse::<u32>().ok())
+            .ok_or_else(|| anyhow!("Could not start the server on {}: {}", host, text.trim()))?;
+        let rest: Vec<&str> = lines.collect();
+        let exited = rest.first() == Some(&"rjy-exited");
+        let server_output = rest.iter().skip(exited as usize).copied().collect::<Vec<&str>>().join("\n");
+        match find_link(&server_output, None) {
+            Ok(found) if !exited => Ok((found.link, pid)),
+            _ if exited => Err(anyhow!("The command exited on {} without starting a server. Its output \
+                                       (also in {} there):\n{}", host, log, server_output.trim())),
+            _ => Err(anyhow!("The server on {} (pid {}) printed no link within {}s; it's still running, \
+                             with its output in {} there.", host, pid, REMOTE_LAUNCH_WAIT_SECS, log))
+        }
+    }
+
+    /// Collect the exit status of this session's tunnel if it was started by
+    /// this process and has exited; otherwise it lingers as a zombie that
+    /// still looks alive.
+    pub fn reap(&self) {
+        self.reap_pid(self.pid);
+    }
+
+    fn reap_pid(&self, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            // Not our child (e.g. started by an earlier rjy) is fine too.
+            let _ = waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG));
+        }
+    }
+
+    /// Wait for the tunnel to accept connections on its local port, giving up
+    /// if ssh exits or `timeout` passes.
+    pub fn wait_until_up(&self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        let host = unbracket(&self.local_host()).to_string();
+        while started.elapsed() < timeout {
+            self.reap();
+            if !self.is_alive() {
+                return false;
+            }
+            if self.is_listening(&host) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+        false
+    }
+
+    /// Whether anything accepts connections on the tunnel's local port.
+    pub fn is_listening(&self, host: &str) -> bool {
+        (host, self.port).to_socket_addrs()
+            .map(|mut addrs| addrs.any(|addr| {
+                TcpStream::connect_timeout(&addr, Duration::from_millis(250)).is_ok()
+            }))
+            .unwrap_or(false)
+    }
+
+    pub fn run_on_disconnect(&self) -> Result<()> {
+        self.run_hook("on-disconnect", self.on_disconnect_cmd.as_deref())
+    }
+
+    pub fn run_on_reconnect(&self) -> Result<()> {
+        self.run_hook("on-reconnect", self.on_reconnect_cmd.as_deref())
+    }
+
+    /// Run one of the session's hook commands, if it has one, without waiting
+    /// for it to finish.
+    fn run_hook(&self, hook: &str, cmd: Option<&str>) -> Result<()> {
+        let Some(cmd) = cmd else {
+            return Ok(());
+        };
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("RJYKEY", self.key())
+            .env("RJYHOST", &self.host)
+            .env("RJYPORT", self.port.to_string())
+            .spawn()
+            .map_err(|err| anyhow!("Failed to run the {} command for {}: {}", hook, self.key(), err))?;
+        let key = self.key();
+        let hook = hook.to_string();
+        thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                eprintln!("Warning: the {} command for {} exited with {}.", hook, key, status);
+            },
+            Err(err) => eprintln!("Warning: the {} command for {} failed: {}", hook, key, err),
+            Ok(_) => {}
+        });
+        Ok(())
+    }
+
+    /// Start a new tunnel for this session, keeping everything else as is
+    /// (local sessions have none to start).
+    pub fn reconnect(&mut self) -> Result<()> {
+        if self.is_local() {
+            return Ok(());
+        }
+        let pid = match self.persist_ssh {
+            true => self.connect_through_master()?,
+            false => self.new_connection()?
+        };
+        self.pid = Some(pid);
+        self.auto_kill_at = None;
+        if let Some(secs) = self.auto_kill_after_secs {
+            self.kill_after(secs)?;
+        }
+        Ok(())
+    }
+
+    /// Kill the current tunnel `secs` seconds from now, unless it's already
+    /// due to be killed sooner.
+    pub fn kill_after(&mut self, secs: u64) -> Result<()> {
+        let Some(pid) = self.pid else {
+            return Ok(());
+        };
+        let at = Utc::now() + chrono::Duration::seconds(secs as i64);
+        let sooner = self.auto_kill_at.as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|due| due < at);
+        if !sooner {
+            self.auto_kill_at = Some(at.to_rfc3339_opts(SecondsFormat::Secs, true));
+            self.schedule_kill(pid, secs)?;
+        }
+        Ok(())
+    }
+
+    /// Seconds until the current tunnel is killed, if it's due to be.
+    pub fn secs_until_kill(&self) -> Option<i64> {
+        let at = DateTime::parse_from_rfc3339(self.auto_kill_at.as_deref()?).ok()?;
+        Some((at.with_timezone(&Utc) - Utc::now()).num_seconds().max(0))
+    }
+
+    /// One of the SESSION_FIELDS, as shown by `--format`; empty fields
+    /// (e.g. the pid of a disconnected session) are empty strings.
+    pub fn field(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "key" => format_key(self),
+            "name" => self.alias.clone().unwrap_or_default(),
+            "host" => self.host.clone(),
+            "port" => self.link_port().unwrap_or(self.port).to_string(),
+            "local_port" => self.port.to_string(),
+            "status" => self.status().msg(),
+            "pid" => self.get_pid().map_or(String::new(), |p| p.to_string()),
+            "link" => self.link.clone(),
+            "local_url" => self.local_url(),
+            "created_at" => self.created.clone().unwrap_or_default(),
+            "tags" => self.tags.join(","),
+            "note" => self.note.clone().unwrap_or_default(),
+            _ => return None
+        };
+        Some(value)
+    }
+
+    /// The session's front end: as set, or else as the link's path suggests.
+    fn link_format(&self) -> LinkFormat {
+        let link_path = Url::parse(&self.link).map(|url| url.path().to_string()).unwrap_or_default();
+        // sessions from older caches have no format recorded, so detect it here.
+        match self.link_format {
+            LinkFormat::Auto => LinkFormat::detect(&link_path),
+            format => format
+        }
+    }
+
+    /// The URL of the session's front end through the local end of the tunnel.
+    pub fn local_url(&self) -> String {
+        let link_path = Url::parse(&self.link).map(|url| url.path().to_string()).unwrap_or_default();
+        let link_format = self.link_format();
+        // keep the page the link opens (e.g. lab/tree/analysis.ipynb), unless
+        // the session was set to use a different front end than the link's.
+        let (_, link_page) = split_base_url(&link_path);
+        let page = match link_format {
+            _ if !link_page.is_empty() && LinkFormat::detect(&link_path) == link_format => link_page.as_str(),
+            LinkFormat::Auto => "",
+            LinkFormat::Classic => "tree",
+            LinkFormat::Lab | LinkFormat::Hub => "lab"
+        };
+        let mut url = self.local_url_at(page);
+        if let Some(fragment) = &self.fragment {
+            url = format!("{}#{}", url, fragment);
+        }
+        url
+    }
+
+    /// The URL of `page` (e.g. "lab") on the server through the tunnel, with
+    /// the link's query (and so its token).
+    fn local_url_at(&self, page: &str) -> String {
+        let url = format!("{}://{}:{}{}{}", self.scheme(), self.local_host(), self.port, self.base_url(), page);
+        // sessions from older caches only have the token recorded.
+        let query = self.query.clone()
+            .or_else(|| self.token.as_ref().map(|token| format!("token={}", encode_token(token))));
+        match query {
+            Some(query) => format!("{}?{}", url, query),
+            None => url
+        }
+    }
+
+    /// The URL of the session's --ipynb-path notebook, if it has one.
+    pub fn notebook_url(&self) -> Option<String> {
+        let path = self.ipynb_path.as_ref()?;
+        let page = match self.link_format() {
+            LinkFormat::Classic => "notebooks",
+            LinkFormat::Auto | LinkFormat::Lab | LinkFormat::Hub => "lab/tree"
+        };
+        Some(self.local_url_at(&format!("{}/{}", page, path)))
+    }
+
+    /// The --ipynb-path notebook, decoded for display.
+    pub fn ipynb_path(&self) -> Option<String> {
+        self.ipynb_path.as_deref()
+            .map(|path| percent_decode_str(path).decode_utf8_lossy().to_string())
+    }
+
+    /// The path the server is mounted under, always with a trailing slash.
+    pub fn base_url(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            // sessions from older caches don't have this recorded.
+            None => {
+                let link_path = Url::parse(&self.link).map(|url| url.path().to_string()).unwrap_or_default();
+                split_base_url(&link_path).0
+            }
+        }
+    }
+
+    /// The root of the Jupyter server through the tunnel, which its API is under.
+    pub fn server_url(&self) -> String {
+        format!("{}://{}:{}{}", self.scheme(), self.local_host(), self.port, self.base_url().trim_end_matches('/'))
+    }
+
+    pub fn scheme(&self) -> String {
+        match &self.scheme {
+            Some(scheme) => scheme.clone(),
+            // sessions from older caches don't have this recorded.
+            None => match Url::parse(&self.link) {
+                Ok(url) if url.scheme() == "https" && url.port().is_some() => "https".to_string(),
+                _ => "http".to_string()
+            }
+        }
+    }
+
+    /// The token, or an empty string if there isn't one.
+    pub fn token_str(&self) -> &str {
+        self.token.as_deref().unwrap_or("")
+    }
+
+    /// The session's token, or an error saying why there isn't one.
+    pub fn get_token(&self) -> Result<String> {
+        match (&self.token, self.token_in_keychain) {
+            (Some(token), _) => Ok(token.clone()),
+            (None, true) => Err(anyhow!("Could not read the token of session {} from the keychain.",
+                                        format_key(self))),
+            (None, false) => Err(anyhow!("Session {} has no token (its server uses a password, or no \
+                                         authentication).", format_key(self)))
+        }
+    }
+
+    /// Move the token into the OS keychain.
+    pub fn move_token_to_keychain(&mut self) -> Result<()> {
+        keychain_store(&self.key(), &self.get_token()?)?;
+        self.token_in_keychain = true;
+        Ok(())
+    }
+
+    /// Fill in the token of a session that keeps it in the keychain.
+    fn load_token_from_keychain(&mut self) -> Result<()> {
+        let token = keychain_lookup(&self.key())?;
+        self.link = unredact_token(&self.link, &token);
+        self.query = self.query.as_deref().map(|query| unredact_token(query, &token));
+        self.token = Some(token);
+        Ok(())
+    }
+
+    /// The session as written to the cache: for one that keeps its token in
+    /// the keychain, with the token taken out.
+    fn for_cache(&self) -> Connection {
+        let mut conn = self.clone();
+        if let (true, Some(token)) = (self.token_in_keychain, &self.token) {
+            conn.link = redact_token(&self.link, token);
+            conn.query = self.query.as_deref().map(|query| redact_token(query, token));
+            conn.token = None;
+        }
+        conn
+    }
+
+    /// The link as shown to the user, noting when there is no token.
+    pub fn display_link(&self) -> String {
+        match self.token {
+            None => format!("{} (token: none)", self.link),
+            Some(_) => self.link.clone()
+        }
+    }
+
+    /// A `rjy new` command line that would recreate this session.
+    pub fn show_cmd(&self, with_token: bool) -> String {
+        let command = match &self.origin {
+            Some(origin) if matches!(origin.command.get(1).map(|c| c.as_str()),
+                                     Some("new") | Some("new-raw")) => {
+                origin.command.clone()
+            },
+            _ => {
+                let link = redact_token(&self.link, self.token_str());
+                vec!["rjy".to_string(), "new".to_string(), link, self.host.clone()]
+            }
+        };
+        command.iter()
+            .map(|arg| match with_token {
+                true => unredact_token(arg, self.token_str()),
+                false => arg.clone()
+            })
+            .map(|arg| shell_quote(&arg))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn key(&self) -> String {
+        format_key(self)
+    }
+
+    fn kill_connection(&mut self) -> Result<()> {
+        if self.is_local() {
+            println!("Session {} is a local connection, with no SSH tunnel to close.", self.key());
+            return Ok(());
+        }
+        match self.pid {
+            None => {
+                println!("Connection has already closed.");
+            },
+            Some(p) => match self.tunnel_running() {
+                true => {
+                    let pid = Pid::from_raw(p as i32);
+                    // Send the SIGTERM signal
+                    kill(pid, Signal::SIGTERM)?;
+                    println!("Disconnected session {}:{} (Process ID={}).", self.host, self.port, p);
+                    self.kill_master(p)?;
+                },
+                false => {
+                    println!("Connection has already closed.");
+                }
+            }
+        }
+        self.pid = None;
+        Ok(())
+    }
+
+    /// Close the session's --persist-ssh master connection too, if it's a
+    /// process other than the tunnel `tunnel_pid` just killed.
+    fn kill_master(&mut self, tunnel_pid: u32) -> Result<()> {
+        if let Some(master) = self.master_pid.take().filter(|&master| master != tunnel_pid) {
+            let pid = Pid::from_raw(master as i32);
+            if is_pid_running(pid) {
+                kill(pid, Signal::SIGTERM)?;
+                println!("Closed the SSH master connection (Process ID={}).", master);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A desired session, as described in a sessions file for `rjy apply`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSpec {
+    pub host: String,
+    pub link: String,
+    #[serde(flatten)]
+    pub options: NewOptions
+}
+
+impl SessionSpec {
+    pub fn load(path: &PathBuf) -> Result<Vec<SessionSpec>> {
+        let mut file = File::open(path)
+            .map_err(|err| anyhow!("Failed to open sessions file '{:?}': {}", path, err))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let mut specs: Vec<SessionSpec> = serde_yaml::from_str(&contents)
+            .map_err(|err| anyhow!("Failed to parse sessions file '{:?}': {}", path, err))?;
+        for spec in specs.iter_mut() {
+            spec.link = clean_link(&spec.link);
+        }
+        Ok(specs)
+    }
+}
+
+/// One step needed to bring the cache in line with a sessions file.
+pub enum PlanAction {
+    Create(String, SessionSpec),
+    Update(String, SessionSpec),
+    Reconnect(String),
+    Drop(String),
+    Unchanged(String)
+}
+
+impl PlanAction {
+    pub fn msg(&self) -> String {
+        match self {
+            PlanAction::Create(key, _) => format!("+ create     {}", key),
+            PlanAction::Update(key, _) => format!("~ update     {} (link changed)", key),
+            PlanAction::Reconnect(key) => format!("~ reconnect  {}", key),
+            PlanAction::Drop(key) => format!("- drop       {}", key),
+            PlanAction::Unchanged(key) => format!("  unchanged  {}", key)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionCache {
+    connections: HashMap<String,Connection>
+}
+
+/// Where rjy keeps its data: $XDG_DATA_HOME, or ~/.local/share.
+fn data_dir() -> Result<PathBuf> {
+    match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(PathBuf::from(env::var("HOME")?).join(".local/share"))
+    }
+}
+
+fn config_dir() -> Result<PathBuf> {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(PathBuf::from(env::var("HOME")?).join(".config"))
+    }
+}
+
+/// Settings from the config file; anything not set there takes its default.
+/// Unknown settings (e.g. misspelled ones) are errors.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Open sessions in the browser after 'rjy new' and 'rjy rc', as --open does.
+    pub auto_open: bool,
+    /// The session 'rjy get' and 'rjy open' use when not given a key.
+    pub default_session: Option<String>,
+    /// The columns 'rjy list' shows when not given --fields.
+    pub list_fields: Option<Vec<String>>,
+    /// The ssh program to run, e.g. a wrapper script, instead of ssh on the PATH.
+    pub ssh_binary: Option<PathBuf>,
+    /// Give new sessions without a note one from their host and the
+    /// current time, as --auto-label does.
+    pub auto_label: bool
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf> {
+        Ok(config_dir()?.join(CONFIG))
+    }
+
+    pub fn load() -> Result<Config> {
+        let path = Config::path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(&path)?;
+        toml::from_str(&contents)
+            .map_err(|err| anyhow!("Failed to parse config file '{:?}': {}", path, err))
+    }
+
+    /// Check the config file, beyond what loading it checks, e.g. that
+    /// list_fields only names known fields.
+    pub fn validate() -> Result<()> {
+        let path = Config::path()?;
+        if !path.exists() {
+            println!("There's no config file at {:?}, so the defaults apply.", path);
+            return Ok(());
+        }
+        let config = Config::load()?;
+        if let Some(fields) = &config.list_fields {
+            parse_list_fields(fields).map_err(|err| anyhow!("In list_fields in {:?}: {}", path, err))?;
+        }
+        // a bare name is looked up on the PATH.
+        if let Some(ssh) = &config.ssh_binary {
+            if ssh.components().count() > 1 && !ssh.is_file() {
+                return Err(anyhow!("In ssh_binary in {:?}: there's no file at {:?}.", path, ssh));
+            }
+        }
+        if let Some(key) = &config.default_session {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            if sessions.resolve_key(key).is_err() {
+                eprintln!("Warning: default_session in {:?} is '{}', but there's no such session (yet).", path, key);
+            }
+        }
+        println!("The config file {:?} is valid.", path);
+        Ok(())
+    }
+}
+
+/// What `rjy config` does.
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Check the config file for unknown settings, values of the wrong
+    /// type, and unknown list fields, failing if there are any.
+    Validate
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub operation: String,
+    pub key: String,
+    pub host: String,
+    pub pid: Option<u32>
+}
+
+/// An append-only log of the changes made to sessions, one JSON object per
+/// line.
+pub struct AuditLogger {
+    path: PathBuf
+}
+
+impl AuditLogger {
+    pub fn new() -> Result<Self> {
+        Ok(AuditLogger { path: data_dir()?.join(AUDIT_LOG) })
+    }
+
+    pub fn log(&self, op: &str, conn: &Connection) -> Result<()> {
+        let entry = AuditEntry {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            operation: op.to_string(),
+            key: conn.key(),
+            host: conn.host.clone(),
+            pid: conn.pid
+        };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        set_permissions(&self.path, Permissions::from_mode(0o600))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// Log `op`, warning rather than failing if the log can't be written, so
+    /// the change itself still goes through.
+    pub fn record(op: &str, conn: &Connection) {
+        if let Err(err) = AuditLogger::new().and_then(|logger| logger.log(op, conn)) {
+            eprintln!("Warning: could not write to the audit log: {}", err);
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<AuditEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)?;
+        let mut entries = Vec::new();
+        for (i, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => eprintln!("Warning: skipping line {} of the audit log: {}", i + 1, err)
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Print the last `lines` entries (all, if `None`), optionally only those
+    /// for session `key`, with terminal styling only if `colour` is set.
+    pub fn show(&self, lines: Option<usize>, key: Option<&str>, colour: bool) -> Result<()> {
+        let mut entries = self.entries()?;
+        if let Some(key) = key {
+            entries.retain(|entry| entry.key == key);
+        }
+        if let Some(lines) = lines {
+            entries.drain(..entries.len().saturating_sub(lines));
+        }
+        if entries.is_empty() {
+            println!("No matching entries in the audit log.");
+            return Ok(());
+        }
+        let mut table = Table::new();
+        table.set_titles(row!["Time", "Operation", "Key (host:port)", "Process ID"]);
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        for entry in entries.iter() {
+            let pid = entry.pid.map_or(" ".to_string(), |p| p.to_string());
+            table.add_row(row![entry.timestamp, entry.operation, entry.key, pid]);
+        }
+        match colour {
+            true => table.printstd(),
+            false => {
+                table.print(&mut io::stdout())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConnectionCache {
+    fn cache_path() -> Result<PathBuf> {
+        Ok(data_dir()?.join(CACHE))
+    }
+
+    /// Move a cache from its old place in $HOME to the XDG data directory,
+    /// leaving the old file renamed with a `.migrated` suffix.
+    fn migrate_old_cache(cache_path: &PathBuf) -> Result<()> {
+        let old_path = PathBuf::from(env::var("HOME")?).join(OLD_CACHE);
+        if cache_path.exists() || !old_path.exists() {
+            return Ok(());
+        }
+        if let Some(dir) = cache_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(&old_path, cache_path)
+            .map_err(|err| anyhow!("Failed to migrate the cache from {:?}: {}", old_path, err))?;
+        set_permissions(cache_path, Permissions::from_mode(0o600))?;
+        let mut migrated = old_path.clone().into_os_string();
+        migrated.push(".migrated");
+        fs::rename(&old_path, &migrated)?;
+        eprintln!("Moved the remote Jupyter session cache from {:?} to {:?}.", old_path, cache_path);
+        Ok(())
+    }
+
+    pub fn new() -> Self {
+        ConnectionCache {
+            connections: HashMap::new()
+        }
+    }
+
+    /// Read another cache file, e.g. one copied from another machine.
+    pub fn load_file(path: &PathBuf) -> Result<ConnectionCache> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read session cache '{:?}': {}", path, err))?;
+        let cache: HashMap<String,Connection> = match contents.trim().is_empty() {
+            true => HashMap::new(),
+            false => serde_yaml::from_str(&contents)
+                .map_err(|err| anyhow!("Failed to parse session cache '{:?}': {}", path, err))?
+        };
+        Ok(ConnectionCache {
+            connections: cache.into_values().map(|conn| (conn.key(), conn)).collect()
+        })
+    }
+
+    pub fn load(&mut self) -> Result<()> {
+        let cache_path = ConnectionCache::cache_path()?;
+        ConnectionCache::migrate_old_cache(&cache_path)?;
+        // if we try to load the file and it doesn't exist, 
+        // just create an empty cache.
+        if !cache_path.exists() {
+            self.connections = HashMap::new();
+            self.save()?;
+            return Ok(())
+        }
+
+        let mut file = File::open(cache_path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let cache: HashMap<String,Connection> = if contents.trim().is_empty() {
+            // a corner case: cache file exists but is empty. Handle same way
+            // as if the file does not exist.
+            self.connections = HashMap::new();
+            self.save()?;
+            return Ok(())
+        } else {
+            serde_yaml::from_str(&contents)?
+        };
+
+        // key by each session's current key, in case older rjy wrote it another
+        // way (e.g. an IPv6 host without brackets).
+        self.connections = cache.into_values().map(|conn| (conn.key(), conn)).collect();
+        for (key, conn) in self.connections.iter_mut().filter(|(_, conn)| conn.token_in_keychain) {
+            if let Err(err) = conn.load_token_from_keychain() {
+                eprintln!("Warning: could not read the token of session {} from the keychain: {}", key, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// The keys of all sessions with `tag`, or an error if there are none.
+    pub fn keys_with_tag(&self, tag: &str) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self.connections.iter()
+            .filter(|(_, conn)| conn.tags.iter().any(|t| t == tag))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if keys.is_empty() {
+            return Err(anyhow!("No remote Jupyter sessions are tagged '{}'.", tag));
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Print the sessions `filter` picks out to `out`, in `order`.
+    pub fn list(&self, out: &mut dyn Write, filter: &SessionFilter, order: &SortOrder,
+                output: OutputFormat, template: Option<&Template>, layout: &TableLayout) -> Result<()> {
+        if self.connections.is_empty() && template.is_some() {
+            return Ok(());
+        }
+        if self.connections.is_empty() {
+            match output {
+                OutputFormat::Table | OutputFormat::TableNoColor => writeln!(out, "No active remote Jupyter sessions.")?,
+                OutputFormat::Markdown => writeln!(out, "No active sessions.")?,
+                OutputFormat::Json => writeln!(out, "[]")?,
+                OutputFormat::Porcelain(_) | OutputFormat::Keys | OutputFormat::Long { .. } => {}
+            }
+            return Ok(());
+        }
+        if let Some(tag) = &filter.tag {
+            // fail if no session has the tag, rather than list nothing.
+            self.keys_with_tag(tag)?;
+        }
+        let (mut keys, hidden): (Vec<String>, Vec<String>) = self.connections.keys().cloned()
+            .partition(|key| filter.matches(&self.connections[key]));
+        self.sort_keys(&mut keys, order);
+        if let Some(template) = template {
+            for key in keys.iter() {
+                writeln!(out, "{}", template.render(&self.connections[key]))?;
+            }
+            return Ok(());
+        }
+        match output {
+            OutputFormat::Markdown => {
+                self.list_markdown(out, &keys)?;
+                return Ok(());
+            },
+            OutputFormat::Json => {
+                let listing: Vec<SessionListing> = keys.iter()
+                    .map(|key| SessionListing::new(key, &self.connections[key]))
+                    .collect();
+                writeln!(out, "{}", serde_json::to_string_pretty(&listing)?)?;
+                return Ok(());
+            },
+            OutputFormat::Long { show_tokens } => {
+                self.list_long(out, &keys, show_tokens)?;
+                return Ok(());
+            },
+            OutputFormat::Keys => {
+                for key in keys.iter() {
+                    writeln!(out, "{}", key)?;
+                }
+                return Ok(());
+            },
+            OutputFormat::Porcelain(PorcelainVersion::V1) => {
+                for key in keys.iter() {
+                    writeln!(out, "{}", porcelain_v1_line(key, &self.connections[key]))?;
+                }
+                return Ok(());
+            },
+            OutputFormat::Table | OutputFormat::TableNoColor => {}
+        }
+        let fields = &layout.fields;
+        let titles: Vec<&str> = fields.iter()
+            .map(|field| match field.as_str() {
+                "name" => "Name",
+                "key" => "Key (host:port)",
+                "status" => "Status",
+                "pid" => "Process ID",
+                "auto" => "Auto",
+                "age" => "Age",
+                "host" => "Host",
+                "port" => "Port",
+                "local-port" => "Local port",
+                "link" => "Link",
+                "tags" => "Tags",
+                "note" => "Note",
+                "remote-host" => "Remote host",
+                other => other
+            })
+            .collect();
+        let mut rows: Vec<(&Connection, ConnectionStatus, Vec<String>)> = Vec::new();
+        for key in keys.iter() {
+            let conn = &self.connections[key];
+            conn.warn_if_overdue();
+            let marks: Vec<&str> = [(conn.is_local(), "local"), (conn.pinned, "pinned"),
+                                    (conn.persistent, "persistent")].iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, mark)| *mark)
+                .collect();
+            let key_cell = match marks.is_empty() {
+                true => key.to_string(),
+                false => format!("{} ({})", key, marks.join(", "))
+            };
+            let status = conn.status();
+            let pid = conn.get_pid().map_or(" ".to_string(), |p| p.to_string());
+            let age = conn.created.as_deref()
+                .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+                .map_or(String::new(), |created| format_age((Utc::now() - created.with_timezone(&Utc)).num_seconds()));
+            let note = conn.note.as_deref().unwrap_or("");
+            let cells: Vec<String> = fields.iter()
+                .map(|field| match field.as_str() {
+                    "name" => conn.alias.clone().unwrap_or_default(),
+                    "key" => key_cell.clone(),
+                    "status" => format!("{} {}", status.glyph(), status.msg()),
+                    "pid" => pid.clone(),
+                    "auto" => (if conn.auto { "on" } else { "off" }).to_string(),
+                    "age" => age.clone(),
+                    "host" => conn.host.clone(),
+                    "port" => conn.link_port().unwrap_or(conn.port).to_string(),
+                    "local-port" => conn.port.to_string(),
+                    "link" => conn.display_link(),
+                    "tags" => conn.tags.join(","),
+                    "note" if layout.width.is_some() => truncate(note, NOTE_WIDTH),
+                    "note" => note.to_string(),
+                    "remote-host" => conn.remote_fqdn.clone().unwrap_or_default(),
+                    _ => String::new()
+                })
+                .collect();
+            rows.push((conn, status, cells));
+        }
+        let mut widths: Vec<usize> = titles.iter().map(|title| title.chars().count()).collect();
+        for (_, _, cells) in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        if let Some(width) = layout.width {
+            // each column is padded by a space on either side, with a '|'
+            // between them.
+            let mut excess = (widths.iter().sum::<usize>() + 3 * widths.len()).saturating_sub(width + 1);
+            for (shrinkable, narrowest) in SHRINK_ORDER {
+                for (i, _) in fields.iter().enumerate().filter(|(_, field)| *field == shrinkable) {
+                    let narrowest = narrowest.max(titles[i].chars().count());
+                    let cut = excess.min(widths[i].saturating_sub(narrowest));
+                    widths[i] -= cut;
+                    excess -= cut;
+                }
+            }
+        }
+        // laid out by hand, as prettytable's FORMAT_NO_BORDER_LINE_SEPARATOR
+        // would, since it counts a hyperlink's escapes as text.
+        let titles: Vec<String> = titles.iter().zip(&widths)
+            .map(|(title, width)| format!("{:width$}", title, width = width))
+            .collect();
+        writeln!(out, " {} ", titles.join(" | "))?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+        writeln!(out, "{}", rule.join("+"))?;
+        for (conn, status, cells) in rows.iter() {
+            let cells: Vec<String> = fields.iter().zip(cells).zip(&widths)
+                .map(|((field, cell), &width)| {
+                    let text = match field.as_str() {
+                        "link" => short_link(cell, width),
+                        _ => truncate(cell, width)
+                    };
+                    let padding = " ".repeat(width.saturating_sub(text.chars().count()));
+                    match field.as_str() {
+                        "status" if layout.colour => {
+                            let style = match status {
+                                ConnectionStatus::Connected => "\x1b[1;32m",
+                                ConnectionStatus::Disconnected => "\x1b[1;31m"
+                            };
+                            format!("{}{}\x1b[0m{}", style, text, padding)
+                        },
+                        "link" if layout.hyperlinks => {
+                            format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\{}", conn.local_url(), text, padding)
+                        },
+                        _ => text + &padding
+                    }
+                })
+                .collect();
+            writeln!(out, " {} ", cells.join(" | "))?;
+        }
+        if !hidden.is_empty() {
+            let alive = hidden.iter().filter(|key| self.connections[*key].is_alive()).count();
+            let states = match (alive, hidden.len() - alive) {
+                (alive, 0) => format!("{} alive", alive),
+                (0, dead) => format!("{} dead", dead),
+                (alive, dead) => format!("{} alive and {} dead", alive, dead)
+            };
+            let noun = if hidden.len() == 1 { "session" } else { "sessions" };
+            writeln!(out, "({} {} hidden)", states, noun)?;
+        }
+        Ok(())
+    }
+
+    /// Put `keys` in the order `rjy list --sort` asks for, comparing the
+    /// sessions' values (not as they're shown), with host and port breaking
+    /// ties so the order is stable.
+    fn sort_keys(&self, keys: &mut [String], order: &SortOrder) {
+        let time = |t: &Option<String>| t.as_deref().and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+        // sessions without a timestamp go last.
+        let by_time = |a: Option<DateTime<FixedOffset>>, b: Option<DateTime<FixedOffset>>| match (a, b) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some())
+        };
+        let alive: HashMap<&str, bool> = match order.sort {
+            SortKey::Status => keys.iter()
+                .filter_map(|key| self.connections.get_key_value(key))
+                .map(|(key, conn)| (key.as_str(), conn.is_alive()))
+                .collect(),
+            _ => HashMap::new()
+        };
+        keys.sort_by(|a, b| {
+            let (conn_a, conn_b) = (&self.connections[a], &self.connections[b]);
+            let by = match order.sort {
+                SortKey::Host => Ordering::Equal,
+                SortKey::Port => conn_a.port.cmp(&conn_b.port),
+                // connected first.
+                SortKey::Status => alive[b.as_str()].cmp(&alive[a.as_str()]),
+                SortKey::Created => by_time(time(&conn_a.created), time(&conn_b.created)),
+                SortKey::LastUsed => by_time(time(&conn_a.last_used), time(&conn_b.last_used))
+            };
+            by.then_with(|| (&conn_a.host, conn_a.port).cmp(&(&conn_b.host, conn_b.port)))
+        });
+        if order.reverse {
+            keys.reverse();
+        }
+    }
+
+    /// Print each session as a block of lines, e.g. to review them all
+    /// before a cleanup. Tokens are left out unless `show_tokens`.
+    fn list_long(&self, out: &mut dyn Write, keys: &[String], show_tokens: bool) -> Result<()> {
+        for (i, key) in keys.iter().enumerate() {
+            let conn = &self.connections[key];
+            conn.warn_if_overdue();
+            let hide = |text: &str| match show_tokens {
+                true => text.to_string(),
+                false => redact_token(text, conn.token_str())
+            };
+            if i > 0 {
+                writeln!(out)?;
+            }
+            match conn.get_pid() {
+                Some(pid) => writeln!(out, "{} (connected, pid {})", key, pid)?,
+                None => writeln!(out, "{} (disconnected)", key)?
+            }
+            match &conn.remote_fqdn {
+                Some(fqdn) => writeln!(out, "  Host:        {} ({})", conn.host, fqdn)?,
+                None => writeln!(out, "  Host:        {}", conn.host)?
+            }
+            if let Some(alias) = &conn.alias {
+                writeln!(out, "  Name:        {}", alias)?;
+            }
+            writeln!(out, "  Link:        {}", hide(&conn.display_link()))?;
+            writeln!(out, "  Local URL:   {}", hide(&conn.local_url()))?;
+            writeln!(out, "  SSH command: ssh {}", conn.ssh_args().join(" "))?;
+            writeln!(out, "  Created:     {}", conn.created.as_deref().unwrap_or("(unknown)"))?;
+            writeln!(out, "  Last used:   {}", conn.last_used.as_deref().unwrap_or(""))?;
+            writeln!(out, "  Tags:        {}", conn.tags.join(","))?;
+            writeln!(out, "  Note:        {}", conn.note.as_deref().unwrap_or(""))?;
+        }
+        Ok(())
+    }
+
+    /// Print sessions as a Markdown table, leaving out links so no tokens
+    /// end up in a bug report.
+    fn list_markdown(&self, out: &mut dyn Write, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            writeln!(out, "No active sessions.")?;
+            return Ok(());
+        }
+        writeln!(out, "| Key | Status | Host | Port | Created | Description |")?;
+        writeln!(out, "|-----|--------|------|------|---------|-------------|")?;
+        for key in keys.iter() {
+            let conn = &self.connections[key];
+            writeln!(out, "| {} | {} | {} | {} | {} | {} |",
+                     markdown_cell(key), conn.status().msg(), markdown_cell(&conn.host), conn.port,
+                     conn.created.as_deref().unwrap_or(""),
+                     markdown_cell(conn.note.as_deref().unwrap_or("")))?;
+        }
+        Ok(())
+    }
+
+    pub fn reconnect(&mut self, key: &str) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        if conn.is_local() {
+            println!("Session {} is a local connection — no SSH tunnel to reconnect.", key);
+            return Ok(());
+        }
+        conn.reconnect()?;
+        AuditLogger::record("rc", conn);
+        println!("Reconnected session {}.", key);
+        Ok(())
+    }
+
+    /// Reconnect every session with auto-reconnect on, returning their keys.
+    pub fn reconnect_all(&mut self) -> Result<Vec<String>> {
+        let keys: Vec<String> = self.connections.keys().cloned().collect();
+        let mut reconnected = Vec::new();
+        for key in keys {
+            if !self.connections[&key].auto {
+                println!("Skipping session {} (auto-reconnect is off).", key);
+                continue;
+            }
+            if self.connections[&key].is_local() {
+                println!("Skipping session {} (local connection, no tunnel).", key);
+                continue;
+            }
+            self.reconnect(&key)?;
+            reconnected.push(key);
+        }
+        Ok(reconnected)
+    }
+
+    /// Add the sessions from `other` that this cache doesn't have, returning
+    /// the keys both have with different sessions, to be resolved (they're
+    /// left as they are until then). Tunnels from `other` aren't this
+    /// machine's, so merged sessions start disconnected.
+    pub fn merge(&mut self, other: ConnectionCache) -> Vec<MergeConflict> {
+        let mut conflicts = Vec::new();
+        let mut keys: Vec<String> = other.connections.keys().cloned().collect();
+        keys.sort();
+        let mut other = other.connections;
+        for key in keys {
+            let mut remote = other.remove(&key).expect("key from the same map");
+            remote.pid = None;
+            remote.master_pid = None;
+            remote.auto_kill_at = None;
+            match self.connections.get(&key) {
+                None => {
+                    if let Some((taken, _)) = self.connections.iter().find(|(_, c)| c.port == remote.port) {
+                        eprintln!("Skipping session {}: session {} already uses local port {}.",
+                                  key, taken, remote.port);
+                        continue;
+                    }
+                    if let Some(alias) = remote.alias.take() {
+                        match self.check_name(&alias) {
+                            Ok(()) => remote.alias = Some(alias),
+                            Err(err) => eprintln!("Warning: session {} is added without its name: {}", key, err)
+                        }
+                    }
+                    AuditLogger::record("merge", &remote);
+                    println!("Added session {}.", key);
+                    self.connections.insert(key, remote);
+                },
+                // the same session, apart from this machine's tunnel and use of it.
+                Some(local) if *local == Connection {
+                    pid: local.pid,
+                    auto_kill_at: local.auto_kill_at.clone(),
+                    last_used: local.last_used.clone(),
+                    ..remote.clone()
+                } => {},
+                Some(local) => conflicts.push(MergeConflict {
+                    key,
+                    local: local.clone(),
+                    remote,
+                    resolution: MergeResolution::Skip
+                })
+            }
+        }
+        conflicts
+    }
+
+    /// Act on resolved merge conflicts: taking the remote session replaces
+    /// the local one, closing its tunnel.
+    pub fn resolve_conflicts(&mut self, conflicts: Vec<MergeConflict>) -> Result<()> {
+        for conflict in conflicts {
+            match conflict.resolution {
+                MergeResolution::KeepRemote => {
+                    let mut local = self.remove_connection(&conflict.key)?;
+                    if local.is_alive() {
+                        local.kill_connection()?;
+                    }
+                    AuditLogger::record("merge", &conflict.remote);
+                    println!("Replaced session {} with the merged one.", conflict.key);
+                    self.connections.insert(conflict.key, conflict.remote);
+                },
+                MergeResolution::KeepLocal => println!("Kept the local session {}.", conflict.key),
+                MergeResolution::Skip => println!("Skipped session {}.", conflict.key)
+            }
+        }
+        Ok(())
+    }
+
+    /// Each tunnel binds its port locally, so no two sessions can share one.
+    pub fn assert_unique_local_ports(&self) -> Result<()> {
+        let mut by_port: HashMap<u16, Vec<&String>> = HashMap::new();
+        for (key, conn) in self.connections.iter() {
+            by_port.entry(conn.port).or_default().push(key);
+        }
+        let mut conflicts: Vec<String> = by_port.iter()
+            .filter(|(_, keys)| keys.len() > 1)
+            .map(|(port, keys)| {
+                let mut keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+                keys.sort();
+                format!("local port {} is used by {}", port, keys.join(", "))
+            })
+            .collect();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        conflicts.sort();
+        Err(anyhow!("Sessions must use distinct local ports: {}.\n\
+                    Drop all but one of the conflicting sessions with 'rjy drop <key>'.",
+                    conflicts.join("; ")))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.assert_unique_local_ports()?;
+        let cached: HashMap<&String, Connection> = self.connections.iter()
+            .map(|(key, conn)| (key, conn.for_cache()))
+            .collect();
+        let serialized_cache = serde_yaml::to_string(&cached)
+            .map_err(|err| anyhow::anyhow!("Failed to serialize data manifest: {}", err))?;
+
+        // Create the file, next to the cache so it can replace it in one
+        // rename: a crash mid-write leaves the old cache, not half of one.
+        let cache_path = ConnectionCache::cache_path()?;
+        if let Some(dir) = cache_path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| anyhow::anyhow!("Failed to create directory '{:?}': {}", dir, err))?;
+        }
+        let mut tmp_path = cache_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| anyhow::anyhow!("Failed to open file '{:?}': {}", tmp_path, err))?;
+
+        // set the permissions such that only user has read/write
+        let permissions = Permissions::from_mode(0o600);
+          set_permissions(&tmp_path, permissions)
+        .map_err(|err| anyhow::anyhow!("Failed to set file permissions: {}", err))?;
+
+        // Write the serialized data to the file
+        write!(file, "{}", serialized_cache)
+            .and_then(|_| file.sync_all())
+            .map_err(|err| anyhow::anyhow!("Failed to write the remote Jupyter cache: {}", err))?;
+        fs::rename(&tmp_path, &cache_path)
+            .map_err(|err| anyhow::anyhow!("Failed to replace the remote Jupyter cache: {}", err))?;
+        self.write_completion_keys()
+    }
+
+    /// Write the list of keys shell completion uses.
+    fn write_completion_keys(&self) -> Result<()> {
+        let mut keys: Vec<&String> = self.connections.keys().collect();
+        keys.sort();
+        let path = data_dir()?.join(COMPLETION_KEYS);
+        let contents: String = keys.iter().map(|key| format!("{}\n", key)).collect();
+        fs::write(&path, contents)
+            .map_err(|err| anyhow!("Failed to write the completion keys to {:?}: {}", path, err))
+    }
+
+    /// Create and connect a session, returning its key.
+    pub fn new_connection(&mut self, link: &str, host: &str, options: &NewOptions) -> Result<String> {
+        let url_parts = UrlParts::parse(link)?;
+        let port = url_parts.tunnel_port(options.remote_port)?;
+        let key = make_key(host, port);
+        if self.connections.contains_key(&key) {
+            return Err(anyhow!("A remote Jupyter session with key '{}' is already registered.\n\
+                               If you'd like to reconnect, use 'rjy rc {}'.", &key, &key));
+        }
+        if let Some((other, conn)) = self.connections.iter().find(|(_, c)| c.alias.as_ref() == Some(&key)) {
+            return Err(anyhow!("The key '{}' is already the name of session {} ({}).",
+                               key, other, redact_token(&conn.link, conn.token_str())));
+        }
+        if let Some(alias) = &options.alias {
+            self.check_name(alias)?;
+        }
+        // a local session elsewhere (e.g. over a VPN) takes no port here.
+        let takes_port = |c: &Connection| !c.is_local() || LOOPBACK_ADDRESSES.contains(&c.host.as_str());
+        let new_takes_port = !options.local_only || LOOPBACK_ADDRESSES.contains(&unbracket(host));
+        if let Some((other, _)) = self.connections.iter()
+            .find(|(_, c)| c.port == port && takes_port(c) && new_takes_port) {
+            return Err(anyhow!("Session '{}' already uses local port {}.", other, port));
+        }
+        let mut connection = Connection::new(link, host, options)?;
+        if options.keychain {
+            match connection.token {
+                Some(_) => connection.move_token_to_keychain()?,
+                None => eprintln!("Note: the link has no token to keep in the keychain.")
+            }
+        }
+        AuditLogger::record("new", &connection);
+        self.connections.insert(connection.key(), connection);
+        println!("Created new session {}.", key);
+        Ok(key)
+    }
+    pub fn drop_connection(&mut self, key: &str) -> Result<()> {
+        let mut conn = self.remove_connection(key)?;
+        AuditLogger::record("drop", &conn);
+        if conn.token_in_keychain {
+            if let Err(err) = keychain_delete(key) {
+                eprintln!("Warning: could not remove the token of {} from the keychain: {}", key, err);
+            }
+        }
+        conn.kill_connection()
+    }
+    pub fn remove_connection(&mut self, key: &str) -> Result<Connection> {
+        match self.connections.remove(key) {
+            None => Err(anyhow!("Could not find a remote Jupyter session with key '{}'.", &key)),
+            Some(conn) => Ok(conn)
+        }
+    }
+    /// Leave pinned sessions out of a bulk operation, unless they're included
+    /// explicitly.
+    pub fn bulk_keys(&self, keys: Vec<String>, include_pinned: bool) -> Vec<String> {
+        keys.into_iter()
+            .filter(|key| {
+                let pinned = self.connections.get(key).is_some_and(|conn| conn.pinned);
+                if pinned && !include_pinned {
+                    println!("Skipping pinned session {} (use --include-pinned to include it).", key);
+                }
+                include_pinned || !pinned
+            })
+            .collect()
+    }
+    /// Ask on the terminal which sessions to `action` (e.g. "disconnect"),
+    /// for commands given no key. Without a terminal, ask for a key instead.
+    pub fn pick_sessions(&self, action: &str) -> Result<Vec<String>> {
+        if !io::stdin().is_terminal() {
+            return Err(anyhow!("Give the key of the session to {} (or --all).", action));
+        }
+        let mut keys: Vec<&String> = self.connections.keys().collect();
+        if keys.is_empty() {
+            return Err(anyhow!("There are no sessions to {}.", action));
+        }
+        keys.sort();
+        for (i, key) in keys.iter().enumerate() {
+            let conn = &self.connections[*key];
+            let status = conn.status();
+            let name = conn.alias.as_ref().map(|name| format!(" ({})", name)).unwrap_or_default();
+            println!("  {}) {} {}{}, {}", i + 1, status.glyph(), key, name, status.msg());
+        }
+        print!("Which to {}? (e.g. 1 3, or 2-4) [1-{}] ", action, keys.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        let picked = parse_picks(&answer, keys.len())?;
+        Ok(picked.into_iter().map(|i| keys[i - 1].to_string()).collect())
+    }
+    pub fn set_auto(&mut self, key: &str, auto: bool) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        conn.auto = auto;
+        println!("Auto-reconnect is {} for session {}.", if auto { "on" } else { "off" }, key);
+        Ok(())
+    }
+    pub fn set_persistent(&mut self, key: &str, persistent: bool) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        conn.persistent = persistent;
+        match persistent {
+            true => println!("Session {} is persistent: reconnect-loop won't give up on it.", key),
+            false => println!("Session {} is no longer persistent.", key)
+        }
+        Ok(())
+    }
+    pub fn set_pinned(&mut self, key: &str, pinned: bool) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        conn.pinned = pinned;
+        match pinned {
+            true => println!("Pinned session {}.", key),
+            false => println!("Unpinned session {}.", key)
+        }
+        Ok(())
+    }
+    pub fn drop_all_connections(&mut self, include_pinned: bool) -> Result<()> {
+        let keys: Vec<String> = self.connections.keys().cloned().collect();
+        for key in self.bulk_keys(keys, include_pinned) {
+            self.drop_connection(&key)?;
+        }
+        Ok(())
+    }
+    pub fn disconnect(&mut self, key: &str) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        AuditLogger::record("dc", conn);
+        conn.kill_connection()?;
+        Ok(())
+    }
+    pub fn disconnect_all(&mut self, include_pinned: bool) -> Result<()> {
+        let keys: Vec<String> = self.connections.keys().cloned().collect();
+        for key in self.bulk_keys(keys, include_pinned) {
+            self.disconnect(&key)?;
+        }
+        Ok(())
+    }
+    /// Check that `name` is free to name a session with: that it isn't
+    /// another session's name or key, nor could be mistaken for a key.
+    pub fn check_name(&self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("A session's name can't be empty."));
+        }
+        if canonical_key(name).is_some() {
+            return Err(anyhow!("The name '{}' looks like a key (host:port); choose another.", name));
+        }
+        let taken = self.connections.iter()
+            .find(|(key, conn)| key.as_str() == name || conn.alias.as_deref() == Some(name));
+        match taken {
+            Some((key, conn)) => Err(anyhow!("The name '{}' is already used by session {} ({}).",
+                                             name, key, redact_token(&conn.link, conn.token_str()))),
+            None => Ok(())
+        }
+    }
+
+    /// Find a session by its key or, failing that, by its name. An exact key
+    /// match wins over a name.
+    pub fn connection_by_alias_or_key<'a>(&'a self, s: &str)
+        -> Result<Option<(&'a str, &'a Connection)>> {
+        if let Some((key, conn)) = self.connections.get_key_value(s) {
+            return Ok(Some((key.as_str(), conn)));
+        }
+        if let Some((key, conn)) = canonical_key(s).and_then(|key| self.connections.get_key_value(&key)) {
+            return Ok(Some((key.as_str(), conn)));
+        }
+        let mut matches: Vec<(&'a str, &'a Connection)> = self.connections.iter()
+            .filter(|(_, conn)| conn.alias.as_deref() == Some(s))
+            .map(|(key, conn)| (key.as_str(), conn))
+            .collect();
+        match matches.len() {
+            0 => {},
+            1 => return Ok(matches.pop()),
+            _ => {
+                let mut keys: Vec<&str> = matches.iter().map(|(key, _)| *key).collect();
+                keys.sort();
+                return Err(anyhow!("The name '{}' is used by more than one session ({}); use a key instead.",
+                                   s, keys.join(", ")));
+            }
+        }
+        let matches = self.fuzzy_lookup(s);
+        match matches.as_slice() {
+            [] => Ok(None),
+            [(key, conn)] => {
+                eprintln!("Assuming you meant '{}'.", key);
+                Ok(Some((key, conn)))
+            },
+            _ => {
+                let keys: Vec<&str> = matches.iter().map(|(key, _)| *key).collect();
+                Err(anyhow!("'{}' matches more than one session:\n  {}\nUse the full key instead.",
+                            s, keys.join("\n  ")))
+            }
+        }
+    }
+    /// Sessions on host `s`, or else those whose key starts with `s`, or else
+    /// those whose key contains it, for when `s` isn't a key or name.
+    pub fn fuzzy_lookup<'a>(&'a self, s: &str) -> Vec<(&'a str, &'a Connection)> {
+        let find = |matches: &dyn Fn(&str, &Connection) -> bool| {
+            let mut found: Vec<(&'a str, &'a Connection)> = self.connections.iter()
+                .filter(|(key, conn)| matches(key, conn))
+                .map(|(key, conn)| (key.as_str(), conn))
+                .collect();
+            found.sort_by_key(|(key, _)| *key);
+            found
+        };
+        let by_host = find(&|key, _| key.strip_prefix(s).is_some_and(|port| port.starts_with(':')));
+        if !by_host.is_empty() {
+            return by_host;
+        }
+        let by_prefix = find(&|key, conn| {
+            key.starts_with(s) || conn.alias.as_deref().is_some_and(|name| name.starts_with(s))
+        });
+        match by_prefix.is_empty() {
+            true => find(&|key, _| key.contains(s)),
+            false => by_prefix
+        }
+    }
+
+    /// The key, host, or name closest to `s` by edit distance, if any is
+    /// close enough to be a likely typo of it.
+    pub fn closest_key_or_name(&self, s: &str) -> Option<&str> {
+        let close_enough = 2.max(s.chars().count() / 3);
+        self.connections.iter()
+            .flat_map(|(key, conn)| {
+                let host = key.rsplit_once(':').map(|(host, _)| host);
+                iter::once(key.as_str()).chain(host).chain(conn.alias.as_deref())
+            })
+            .map(|candidate| (edit_distance(s, candidate), candidate))
+            .filter(|(distance, _)| *distance <= close_enough)
+            .min()
+            .map(|(_, candidate)| candidate)
+    }
+
+    /// The key of the default session, if the config file sets one.
+    pub fn default_key(&self) -> Result<Option<String>> {
+        Config::load()?.default_session
+            .map(|key| self.resolve_key(&key))
+            .transpose()
+    }
+
+    /// The key of the session last opened, or else the one last created.
+    pub fn most_recent_key(&self) -> Result<String> {
+        self.connections.iter()
+            .max_by_key(|(_, conn)| (conn.last_used.clone(), conn.created.clone()))
+            .map(|(key, _)| key.clone())
+            .ok_or_else(|| anyhow!("There are no sessions to open."))
+    }
+
+    /// The key of the session that `s` (a key or a name) refers to.
+    pub fn resolve_key(&self, s: &str) -> Result<String> {
+        match self.connection_by_alias_or_key(s)? {
+            None => match self.closest_key_or_name(s) {
+                Some(closest) => Err(anyhow!("Could not find a remote Jupyter session with key or name '{}'; \
+                                             did you mean '{}'?", s, closest)),
+                None => Err(anyhow!("Could not find a remote Jupyter session with key or name '{}'.", s))
+            },
+            Some((key, _)) => Ok(key.to_string())
+        }
+    }
+    pub fn get_connection(&self, key: &str) -> Result<&Connection> {
+        match self.connections.get(key) {
+            None => Err(anyhow!("Could not find a remote Jupyter session with key '{}'.", &key)),
+            Some(conn) => Ok(conn)
+        }
+    }
+    pub fn get_connection_mut(&mut self, key: &str) -> Result<&mut Connection> {
+        match self.connections.get_mut(key) {
+            None => Err(anyhow!("Could not find a remote Jupyter session with key '{}'.", &key)),
+            Some(conn) => Ok(conn)
+        }
+    }
+    pub fn info(&self, key: &str, show_token: bool) -> Result<()> {
+        let conn = self.get_connection(key)?;
+        conn.warn_if_overdue();
+        let pid = conn.get_pid().map_or("".to_string(), |p| p.to_string());
+        println!("Key:         {}", key);
+        println!("Name:        {}", conn.alias.as_deref().unwrap_or(""));
+        println!("Host:        {}", conn.host);
+        if let Some(fqdn) = &conn.remote_fqdn {
+            println!("Remote host: {}", fqdn);
+        }
+        println!("Type:        {}", match conn.connection_type {
+            ConnectionType::Ssh => "ssh tunnel",
+            ConnectionType::Local => "local (no tunnel)"
+        });
+        match conn.is_local() {
+            true => println!("Port:        {}", conn.port),
+            false => println!("Port:        {} (forwarded)", conn.port)
+        }
+        println!("Link port:   {}", conn.link_port().map_or("".to_string(), |p| p.to_string()));
+        println!("Status:      {}", conn.status().msg());
+        println!("Created:     {}", conn.created.as_deref().unwrap_or("(unknown)"));
+        println!("Last used:   {}", conn.last_used.as_deref().unwrap_or(""));
+        println!("Process ID:  {}", pid);
+        println!("Link:        {}", conn.display_link());
+        println!("Local URL:   {}", conn.local_url());
+        if let Some(path) = conn.ipynb_path() {
+            println!("Notebook:    {}", path);
+        }
+        println!("Bind addr:   {}", conn.bind_address());
+        if show_token {
+            println!("Token:       {}", conn.token.as_deref().unwrap_or("(none)"));
+            println!("Encoded:     {}", conn.token.as_deref().map_or("(none)".to_string(), encode_token));
+        }
+        println!("Base URL:    {}", conn.base_url());
+        println!("Root dir:    {}", conn.root_dir.as_deref().unwrap_or(""));
+        println!("Local dir:   {}", conn.working_dir.as_ref().map_or(String::new(), |dir| dir.display().to_string()));
+        println!("On dc:       {}", conn.on_disconnect_cmd.as_deref().unwrap_or(""));
+        println!("On rc:       {}", conn.on_reconnect_cmd.as_deref().unwrap_or(""));
+        if !conn.env_vars.is_empty() {
+            let names: Vec<&str> = conn.env_vars.keys().map(|name| name.as_str()).collect();
+            println!("SSH env:     {}", names.join(", "));
+        }
+        if let Some(secs) = conn.auto_kill_after_secs {
+            println!("Kill after:  {}s", secs);
+        }
+        if let Some(at) = &conn.auto_kill_at {
+            println!("Kill at:     {}", at);
+        }
+        if let Some(cmd) = &conn.remote_cmd {
+            println!("Remote cmd:  {}", cmd);
+            println!("Remote pid:  {}", conn.remote_jupyter_pid.map_or(String::new(), |p| p.to_string()));
+        }
+        println!("Format:      {}", conn.link_format.msg());
+        println!("Pinned:      {}", if conn.pinned { "yes" } else { "no" });
+        println!("Persistent:  {}", if conn.persistent { "yes" } else { "no" });
+        println!("SSH banner:  {}", if conn.capture_banner { "shown" } else { "hidden" });
+        println!("Auto:        {}", if conn.auto { "on" } else { "off" });
+        println!("Agent fwd:   {}", if conn.forward_agent { "yes" } else { "no" });
+        if let Some((remote, local)) = conn.remote_forward {
+            println!("Remote fwd:  port {} on {} -> localhost:{} here", remote, conn.host, local);
+        }
+        if conn.persist_ssh {
+            println!("SSH master:  {}", match (conn.has_master_socket(), &conn.control_socket) {
+                (true, Some(socket)) => format!("up ({})", socket.display()),
+                _ => "down".to_string()
+            });
+        }
+        println!("Tags:        {}", conn.tags.join(", "));
+        println!("Note:        {}", conn.note.as_deref().unwrap_or(""));
+        match &conn.origin {
+            None => println!("Created by:  (unknown, created before rjy recorded this)"),
+            Some(origin) => {
+                println!("Created by:  {}", conn.show_cmd(false));
+                println!("SSH command: ssh {}", origin.ssh_args.iter()
+                         .map(|arg| shell_quote(arg))
+                         .collect::<Vec<String>>()
+                         .join(" "));
+                println!("rjy version: {}", origin.version);
+            }
+        }
+        Ok(())
+    }
+    /// Apply tag edits: `+tag` or `tag` adds a tag, `-tag` removes it.
+    pub fn edit_tags(&mut self, key: &str, changes: &[String]) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        for change in changes {
+            match change.strip_prefix('-') {
+                Some(tag) => conn.tags.retain(|t| t != tag),
+                None => {
+                    let tag = change.strip_prefix('+').unwrap_or(change);
+                    if tag.is_empty() {
+                        return Err(anyhow!("Empty tag in '{}'.", change));
+                    }
+                    if !conn.tags.iter().any(|t| t == tag) {
+                        conn.tags.push(tag.to_string());
+                    }
+                }
+            }
+        }
+        println!("Session {} is tagged: {}", key, conn.tags.join(", "));
+        Ok(())
+    }
+    /// Report whether each session is alive, for monitoring.
+    pub fn healthcheck(&self, format: HealthcheckFormat) -> Result<()> {
+        let mut keys: Vec<&String> = self.connections.keys().collect();
+        keys.sort();
+        let sessions: Vec<SessionHealth> = keys.iter()
+            .map(|key| {
+                let conn = &self.connections[*key];
+                SessionHealth {
+                    key: key.to_string(),
+                    host: conn.host.clone(),
+                    port: conn.port,
+                    alive: conn.is_alive(),
+                    pid: conn.get_pid()
+                }
+            })
+            .collect();
+        let connected = sessions.iter().filter(|session| session.alive).count();
+        let disconnected = sessions.len() - connected;
+        match format {
+            HealthcheckFormat::Prometheus => {
+                println!("# HELP rjy_session_alive Whether the session's SSH tunnel is up.");
+                println!("# TYPE rjy_session_alive gauge");
+                for session in sessions.iter() {
+                    println!("rjy_session_alive{{key=\"{}\",host=\"{}\",port=\"{}\"}} {}",
+                             prometheus_label(&session.key), prometheus_label(&session.host),
+                             session.port, session.alive as u8);
+                }
+                println!("# HELP rjy_session_count The number of sessions in each state.");
+                println!("# TYPE rjy_session_count gauge");
+                println!("rjy_session_count{{status=\"connected\"}} {}", connected);
+                println!("rjy_session_count{{status=\"disconnected\"}} {}", disconnected);
+            },
+            HealthcheckFormat::Json => {
+                let report = HealthReport {
+                    status: if disconnected == 0 { "ok" } else { "degraded" }.to_string(),
+                    connected,
+                    disconnected,
+                    sessions
+                };
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+        Ok(())
+    }
+    /// Rename a session: change its key to `new` if that's a key (host:port,
+    /// with the same port, as that's where its tunnel listens), or else the
+    /// name `old`, keeping the session and its tunnel as they are.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        let key = self.resolve_key(old)?;
+        let conn = self.get_connection(&key)?;
+        if canonical_key(new).is_none() && conn.alias.as_deref() == Some(old) {
+            if new != old {
+                self.check_name(new)?;
+            }
+            let conn = self.get_connection_mut(&key)?;
+            conn.alias = Some(new.to_string());
+            println!("Renamed session {}: name '{}' -> '{}'.", key, old, new);
+            AuditLogger::record("rename", conn);
+            return Ok(());
+        }
+        let Some(new_key) = canonical_key(new) else {
+            return Err(anyhow!("'{}' isn't a key (host:port), and '{}' isn't a name to rename.", new, old));
+        };
+        let (host, port) = new_key.rsplit_once(':').expect("a key has a port");
+        if port != conn.port.to_string() {
+            return Err(anyhow!("Session {}'s tunnel listens on port {}, so its key must keep that port.",
+                               key, conn.port));
+        }
+        if new_key == key {
+            println!("Session {} already has that key.", key);
+            return Ok(());
+        }
+        if let Some((other, _)) = self.connections.iter()
+            .find(|(k, c)| **k == new_key || c.alias.as_deref() == Some(new_key.as_str())) {
+            return Err(anyhow!("Can't rename session {} to {}: session {} already has that key or name.",
+                               key, new_key, other));
+        }
+        // the keychain holds the token under the session's key.
+        if conn.token_in_keychain {
+            keychain_store(&new_key, &conn.get_token()?)?;
+        }
+        let mut conn = self.connections.remove(&key).expect("resolved key");
+        conn.host = unbracket(host).to_string();
+        AuditLogger::record("rename", &conn);
+        if conn.token_in_keychain {
+            if let Err(err) = keychain_delete(&key) {
+                eprintln!("Warning: could not remove the token of {} from the keychain: {}", key, err);
+            }
+        }
+        self.connections.insert(new_key.clone(), conn);
+        println!("Renamed session {} -> {}.", key, new_key);
+        if Config::load().ok().and_then(|config| config.default_session).as_deref() == Some(key.as_str()) {
+            eprintln!("Note: default_session in the config file is still '{}'.", key);
+        }
+        Ok(())
+    }
+
+    /// Print each session name and the key it stands for.
+    pub fn aliases(&self) {
+        let mut aliases: Vec<(&String, &String)> = self.connections.iter()
+            .filter_map(|(key, conn)| conn.alias.as_ref().map(|alias| (alias, key)))
+            .collect();
+        aliases.sort();
+        for (alias, key) in aliases {
+            println!("{} -> {}", alias, key);
+        }
+    }
+    pub fn set_note(&mut self, key: &str, note: Option<String>) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        match &note {
+            None => println!("Cleared the note on session {}.", key),
+            Some(_) => println!("Updated the note on session {}.", key)
+        }
+        conn.note = note;
+        Ok(())
+    }
+    /// Open the session's local URL in the browser, reconnecting it first if
+    /// asked.
+    pub fn open(&mut self, key: &str, reconnect: bool) -> Result<()> {
+        if !self.get_connection(key)?.is_alive() {
+            if !reconnect {
+                return Err(anyhow!("Session {} is disconnected; reconnect it with 'rjy rc {}', or \
+                                   pass --reconnect.", key, key));
+            }
+            self.reconnect(key)?;
+            thread::sleep(Duration::from_secs(RECONNECT_GRACE_SECS));
+            let conn = self.get_connection(key)?;
+            conn.reap();
+            if !conn.is_alive() {
+                return Err(anyhow!("Could not reconnect session {}.", key));
+            }
+        }
+        let conn = self.get_connection_mut(key)?;
+        let url = match conn.notebook_url() {
+            Some(url) => {
+                println!("Opening {} from session {} in the browser.", conn.ipynb_path().unwrap_or_default(), key);
+                url
+            },
+            None => {
+                println!("Opening session {} in the browser.", key);
+                conn.local_url()
+            }
+        };
+        open_in_browser(&url)?;
+        conn.last_used = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+        Ok(())
+    }
+
+    /// Open a session just (re)connected in the browser once its tunnel is
+    /// up. The session is there either way, so this only warns if it can't.
+    pub fn open_when_up(&mut self, key: &str) -> Result<()> {
+        let conn = self.get_connection(key)?;
+        if !conn.wait_until_up(Duration::from_secs(RECONNECT_GRACE_SECS)) {
+            eprintln!("Warning: session {}'s tunnel isn't up, so not opening it.", key);
+            return Ok(());
+        }
+        if !has_browser() {
+            eprintln!("Warning: there's no graphical environment to open session {} in; \
+                      'rjy info {}' shows its URL.", key, key);
+            return Ok(());
+        }
+        if let Err(err) = self.open(key, false) {
+            eprintln!("Warning: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Print a URL (and QR code) for a colleague to open the session with,
+    /// through this machine's end of the tunnel.
+    pub fn share(&mut self, key: &str, duration_secs: Option<u64>) -> Result<()> {
+        let conn = self.get_connection_mut(key)?;
+        if !conn.is_alive() {
+            return Err(anyhow!("Session {} is disconnected; reconnect it with 'rjy rc {}' first.", key, key));
+        }
+        let bind = conn.bind_address();
+        if LOOPBACK_ADDRESSES.contains(&bind) {
+            eprintln!("Warning: session {}'s tunnel only listens on {}, so only this machine can \
+                      use the URL.\nTo share it, recreate the session with e.g. --bind-address 0.0.0.0.",
+                      key, bind);
+        }
+        let url = conn.network_url()?;
+        if let Some(secs) = duration_secs {
+            conn.kill_after(secs)?;
+            AuditLogger::record("share", conn);
+        }
+        println!("{}", url);
+        match conn.secs_until_kill() {
+            Some(secs) => println!("This URL will be valid for approximately {} seconds (tunnel lifetime, \
+                                    not server-enforced).", secs),
+            None => println!("This URL is valid until the tunnel is closed (e.g. 'rjy dc {}').", key)
+        }
+        println!("Anyone with the URL has the token, and can run code as you on {}.", conn.host);
+        if io::stdout().is_terminal() {
+            println!("\n{}", render_qr(url.as_str(), false)?);
+        }
+        Ok(())
+    }
+
+    /// Print a QR code of a session's URL, to open it on e.g. a tablet on
+    /// the same network.
+    pub fn qr(&self, key: &str, ascii: bool) -> Result<()> {
+        let conn = self.get_connection(key)?;
+        let bind = conn.bind_address();
+        if LOOPBACK_ADDRESSES.contains(&bind) {
+            return Err(anyhow!("Session {}'s tunnel only listens on {}, so no other device could open \
+                               its URL.\nRecreate the session with e.g. --bind-address 0.0.0.0 first.",
+                               key, bind));
+        }
+        if !conn.is_alive() {
+            eprintln!("Warning: session {} is disconnected; reconnect it with 'rjy rc {}'.", key, key);
+        }
+        let url = conn.network_url()?;
+        println!("{}", render_qr(url.as_str(), ascii)?);
+        eprintln!("{}", url);
+        Ok(())
+    }
+
+    pub fn run_command(&self, key: &str, command: &[String]) -> Result<()> {
+        let conn = self.get_connection(key)?;
+        if !conn.is_alive() {
+            eprintln!("Warning: session {} is disconnected; running command anyway.", key);
+        }
+        let envs = [
+            ("JUPYTER_URL", conn.server_url()),
+            ("JUPYTER_PORT", conn.port.to_string()),
+        ];
+        let mut child = Command::new(&command[0]);
+        child.args(&command[1..]).envs(envs);
+        match &conn.token {
+            None => child.env_remove("JUPYTER_TOKEN"),
+            Some(token) => child.env("JUPYTER_TOKEN", token)
+        };
+        // exec() only returns if the command could not be started; otherwise
+        // this process is replaced, so the child's exit code becomes ours.
+        let err = child.exec();
+        Err(anyhow!("Failed to run '{}': {}", command[0], err))
+    }
+
+    /// Make a request to a session's Jupyter API through its tunnel, with its
+    /// token, printing the response body; or run curl to make it.
+    pub fn api_request(&self, key: &str, path: &str, method: Option<&str>, data: Option<&str>,
+                       json: bool, exec_curl: bool) -> Result<()> {
+        let conn = self.get_connection(key)?;
+        if !conn.is_alive() {
+            eprintln!("Warning: session {} is disconnected; trying anyway.", key);
+        }
+        let url = format!("{}/{}", conn.server_url(), path.trim_start_matches('/'));
+        // as with curl, sending data makes it a POST unless told otherwise.
+        let method = method.unwrap_or(if data.is_some() { "POST" } else { "GET" });
+        let auth = conn.token.as_ref().map(|token| format!("token {}", token));
+        if exec_curl {
+            let mut curl = Command::new("curl");
+            curl.args(["-sS", "--fail-with-body", "-X", method]);
+            if let Some(auth) = &auth {
+                curl.arg("-H").arg(format!("Authorization: {}", auth));
+            }
+            if let Some(data) = data {
+                curl.args(["-H", "Content-Type: application/json", "-d", data]);
+            }
+            let err = curl.arg(&url).exec();
+            return Err(anyhow!("Failed to run curl: {}", err));
+        }
+        let body = match data.and_then(|data| data.strip_prefix('@')) {
+            Some(file) => Some(fs::read_to_string(file)
+                .map_err(|err| anyhow!("Failed to read the request body from '{}': {}", file, err))?),
+            None => data.map(|data| data.to_string())
+        };
+        let mut request = ureq::request(method, &url);
+        if let Some(auth) = &auth {
+            request = request.set("Authorization", auth);
+        }
+        let response = match body {
+            Some(body) => request.set("Content-Type", "application/json").send_string(&body),
+            None => request.call()
+        };
+        let (response, failed) = match response {
+            Ok(response) => (response, None),
+            Err(ureq::Error::Status(code, response)) => (response, Some(code)),
+            Err(err) if conn.scheme() == "https" => {
+                return Err(anyhow!("{}\nrjy can't make HTTPS requests itself; use --exec-curl.", err));
+            },
+            Err(err) => return Err(anyhow!("Request to {} failed: {}", path, err))
+        };
+        let text = response.into_string()?;
+        match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(value) if json => println!("{}", serde_json::to_string_pretty(&value)?),
+            _ => println!("{}", text)
+        }
+        match failed {
+            Some(code) => Err(anyhow!("{} {} returned HTTP {}.", method, path, code)),
+            None => Ok(())
+        }
+    }
+
+    /// Stop a session's Jupyter server on the remote host, and by default
+    /// drop the session too.
+    pub fn kill_remote(&mut self, key: &str, signal: &str, drop_local: bool) -> Result<()> {
+        if signal.is_empty() || !signal.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!("Invalid signal '{}'; give a name like TERM or a number.", signal));
+        }
+        let conn = self.get_connection(key)?;
+        let host = conn.host.clone();
+        let kill = match conn.remote_jupyter_pid {
+            // a server started with setsid leads its own process group, so
+            // signal the group to get its kernels too.
+            Some(pid) => format!("kill -{sig} -{pid} 2>/dev/null || kill -{sig} {pid}", sig = signal, pid = pid),
+            None => {
+                if !confirm(&format!("Session {} has no remote server pid (it wasn't started with \
+                                      --remote-cmd). Kill all of your Jupyter processes on {}?", key, host))? {
+                    println!("Left the server on {} running.", host);
+                    return Ok(());
+                }
+                format!("pkill -{} -u \"$(whoami)\" -f jupyter", signal)
+            }
+        };
+        let status = ssh_command()
+            .arg(&host)
+            .arg(&kill)
+            .status()
+            .map_err(|err| anyhow!("Failed to run ssh: {}", err))?;
+        if !status.success() {
+            return Err(anyhow!("Could not kill the server on {} ('{}' exited with {}).", host, kill, status));
+        }
+        println!("Killed the Jupyter server of session {} on {}.", key, host);
+        AuditLogger::record("kill-remote", conn);
+        match drop_local {
+            true => self.drop_connection(key),
+            false => {
+                self.get_connection_mut(key)?.remote_jupyter_pid = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// Move every cached token into the OS keychain.
+    pub fn keychain_migrate(&mut self) -> Result<()> {
+        let mut keys: Vec<String> = self.connections.iter()
+            .filter(|(_, conn)| !conn.token_in_keychain && conn.token.is_some())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        if keys.is_empty() {
+            println!("No tokens in the session cache to move.");
+            return Ok(());
+        }
+        for key in keys.iter() {
+            self.get_connection_mut(key)?.move_token_to_keychain()
+                .map_err(|err| anyhow!("Failed to move the token of {} to the keychain: {}", key, err))?;
+            println!("Moved the token of {} to the keychain.", key);
+        }
+        Ok(())
+    }
+
+    /// Check each link in the chain from the cache to the Jupyter server,
+    /// saying what to do about any that's broken.
+    pub fn diagnose(&self, key: &str) -> Result<()> {
+        let report = |ok: bool, check: &str, hint: &str| {
+            println!("[{}] {}", if ok { " ok " } else { "FAIL" }, check);
+            if !ok {
+                println!("       -> {}", hint);
+            }
+            ok
+        };
+        let skip = |check: &str| println!("[skip] {}", check);
+        let key = match self.resolve_key(key) {
+            Ok(key) => key,
+            Err(err) => {
+                report(false, &err.to_string(), "See 'rjy list' for the sessions rjy knows about.");
+                return Err(anyhow!("Diagnosis failed."));
+            }
+        };
+        let conn = self.get_connection(&key)?;
+        let mut failures = 0;
+        report(true, &format!("Session {} is in the cache", key), "");
+
+        let ssh_alive = match conn.get_pid() {
+            Some(pid) => report(!conn.expired(), &format!("SSH process is running (pid {})", pid),
+                                &format!("Its --timeout-kill time is up; run 'rjy rc {}' to start it again.", key)),
+            None => report(false, "SSH process is running",
+                           &format!("Run 'rjy rc {}' to reconnect.", key))
+        };
+        failures += !ssh_alive as u32;
+
+        let host = unbracket(&conn.local_host()).to_string();
+        let bound = conn.is_listening(&host);
+        let hint = match ssh_alive {
+            true => "ssh is running but not listening: it may still be connecting, or have failed to \
+                     bind the port. Try the tunnel by hand to see ssh's errors (see 'rjy show-cmd').",
+            false => "The SSH process may have crashed; reconnecting should bring the port back."
+        };
+        failures += !report(bound, &format!("Local port {} is bound on {}", conn.port, host), hint) as u32;
+        if bound && !ssh_alive {
+            println!("       Note: something other than this session's tunnel is using the port.");
+        }
+
+        let api_checks = ["Jupyter API answers (GET /api)", "Jupyter version", "Token is accepted (GET /api/kernels)"];
+        if !bound || conn.scheme() == "https" {
+            if bound {
+                println!("       rjy can't make HTTPS requests itself; try 'rjy curl {} /api --exec-curl'.", key);
+            }
+            api_checks.iter().for_each(|check| skip(check));
+        } else {
+            let api = ureq::get(&format!("{}/api", conn.server_url())).timeout(Duration::from_secs(5)).call();
+            let (api_ok, version) = match api {
+                Ok(response) => {
+                    report(true, api_checks[0], "");
+                    (true, response.into_string().ok()
+                        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+                        .and_then(|value| value.get("version")?.as_str().map(|v| v.to_string())))
+                },
+                Err(err) => {
+                    failures += 1;
+                    report(false, &format!("{} ({})", api_checks[0], err),
+                           "Jupyter may have stopped on the remote host; reconnecting won't help. \
+                            Restart it, then add the session again with its new link.");
+                    (false, None)
+                }
+            };
+            match version {
+                Some(version) => { report(true, &format!("{} is {}", api_checks[1], version), ""); },
+                None => skip(api_checks[1])
+            }
+            if api_ok {
+                let mut request = ureq::get(&format!("{}/api/kernels", conn.server_url()))
+                    .timeout(Duration::from_secs(5));
+                if let Some(token) = &conn.token {
+                    request = request.set("Authorization", &format!("token {}", token));
+                }
+                let accepted = match request.call() {
+                    Ok(_) => true,
+                    Err(ureq::Error::Status(401 | 403, _)) => false,
+                    Err(err) => {
+                        println!("       Note: GET /api/kernels failed: {}", err);
+                        false
+                    }
+                };
+                failures += !report(accepted, api_checks[2],
+                                    &format!("The server was probably restarted with a new token. Get its \
+                                              new link (e.g. 'rjy new-detect {}'), then 'rjy drop {}' and \
+                                              add it again.", conn.host, key)) as u32;
+            } else {
+                skip(api_checks[2]);
+            }
+        }
+        match failures {
+            0 => {
+                println!("Session {} looks healthy.", key);
+                Ok(())
+            },
+            n => Err(anyhow!("{} check{} failed for session {}.", n, if n == 1 { "" } else { "s" }, key))
+        }
+    }
+
+    /// Work out what needs to change for the cache to match `specs`.
+    pub fn plan(&self, specs: &[SessionSpec], prune: bool,
+                include_pinned: bool) -> Result<Vec<PlanAction>> {
+        let mut plan = Vec::new();
+        let mut wanted = Vec::new();
+        for spec in specs {
+            let url_parts = UrlParts::parse(&spec.link)?;
+            let key = make_key(&spec.host, url_parts.tunnel_port(spec.options.remote_port)?);
+            if wanted.contains(&key) {
+                return Err(anyhow!("Sessions file lists key '{}' more than once.", key));
+            }
+            let action = match self.connections.get(&key) {
+                None => PlanAction::Create(key.clone(), spec.clone()),
+                Some(conn) if conn.link != normalize_link(&spec.link) => PlanAction::Update(key.clone(), spec.clone()),
+                Some(conn) if !conn.is_alive() => PlanAction::Reconnect(key.clone()),
+                Some(_) => PlanAction::Unchanged(key.clone())
+            };
+            plan.push(action);
+            wanted.push(key);
+        }
+        if prune {
+            let mut extra: Vec<String> = self.connections.keys()
+                .filter(|key| !wanted.contains(key))
+                .cloned()
+                .collect();
+            extra.sort();
+            for key in self.bulk_keys(extra, include_pinned) {
+                plan.push(PlanAction::Drop(key));
+            }
+        }
+        Ok(plan)
+    }
+
+    pub fn apply(&mut self, plan: &[PlanAction]) -> Result<()> {
+        for action in plan {
+            match action {
+                PlanAction::Create(_, spec) => {
+                    self.new_connection(&spec.link, &spec.host, &spec.options)?;
+                },
+                PlanAction::Update(key, spec) => {
+                    self.drop_connection(key)?;
+                    self.new_connection(&spec.link, &spec.host, &spec.options)?;
+                },
+                PlanAction::Reconnect(key) => self.reconnect(key)?,
+                PlanAction::Drop(key) => self.drop_connection(key)?,
+                PlanAction::Unchanged(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print the session keys or hosts, one per line, for shell completion. This
+/// only reads the sessions file (never creating or migrating it), and prints
+/// nothing else, not even errors, as the shell would offer them too.
+fn print_candidates(kind: Candidates) {
+    let sessions = match ConnectionCache::cache_path().and_then(|path| ConnectionCache::load_file(&path)) {
+        Ok(sessions) => sessions,
+        Err(_) => return
+    };
+    let mut candidates: Vec<&str> = sessions.connections.iter()
+        .map(|(key, conn)| match kind {
+            Candidates::Keys => key.as_str(),
+            Candidates::Hosts => conn.host.as_str()
+        })
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    let mut out = io::stdout().lock();
+    for candidate in candidates {
+        if writeln!(out, "{}", candidate).is_err() {
+            return;
+        }
+    }
+}
+
+/// The subcommands (by name and alias) with a positional argument filled in
+/// from the sessions file, with its position and what goes there.
+fn dynamic_args() -> Vec<(String, usize, Candidates)> {
+    let mut args = Vec::new();
+    for sub in Cli::command().get_subcommands() {
+        for (position, arg) in sub.get_positionals().enumerate() {
+            let kind = match arg.get_id().as_str() {
+                "key" => Candidates::Keys,
+                "host" => Candidates::Hosts,
+                _ => continue
+            };
+            for name in iter::once(sub.get_name()).chain(sub.get_all_aliases()) {
+                args.push((name.to_string(), position, kind));
+            }
+        }
+    }
+    args
+}
+
+const BASH_DYNAMIC: &str = r#"
+_rjy_with_sessions() {
+    _rjy "$@"
+    local line="${COMP_LINE:0:COMP_POINT}" cur="" cmd="" kind="" position=0 word
+    local -a words
+    read -ra words <<< "${line}"
+    if [[ ${line} != *[[:space:]] && ${#words[@]} -gt 0 ]]; then
+        cur="${words[-1]}"
+        unset 'words[-1]'
+    fi
+    for word in "${words[@]:1}"; do
+        [[ ${word} == -* ]] && continue
+        if [[ -z ${cmd} ]]; then
+            # skip the values of options before the subcommand, e.g. --color.
+            [[ " @COMMANDS@ " == *" ${word} "* ]] && cmd="${word}"
+        else
+            position=$((position + 1))
+        fi
+    done
+    case "${cmd},${position}" in
+        @KEYS@) kind=keys ;;
+        @HOSTS@) kind=hosts ;;
+    esac
+    [[ -z ${kind} || ${cur} == -* ]] && return 0
+    COMPREPLY=( $(compgen -W "$("$1" __complete ${kind} 2>/dev/null)" -- "${cur}") )
+    # bash splits words at ':', so only complete what follows the last one.
+    if [[ ${cur} == *:* && ${COMP_WORDBREAKS} == *:* ]]; then
+        local prefix="${cur%"${cur##*:}"}"
+        COMPREPLY=( "${COMPREPLY[@]#"${prefix}"}" )
+    fi
+}
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+(( $+functions[_rjy_keys] )) ||
+_rjy_keys() {
+    local expl
+    local -a keys
+    keys=(${(f)"$(rjy __complete keys 2>/dev/null)"})
+    _wanted keys expl 'session key' compadd -a keys
+}
+(( $+functions[_rjy_hosts] )) ||
+_rjy_hosts() {
+    local expl
+    local -a hosts
+    hosts=(${(f)"$(rjy __complete hosts 2>/dev/null)"})
+    _wanted hosts expl 'host' compadd -a hosts
+}
+"#;
+
+/// The completion script for `shell`, which for bash, zsh and fish also
+/// offers the current session keys and hosts, from `rjy __complete`.
+fn completion_script(shell: Shell) -> Result<String> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "rjy", &mut script);
+    let script = String::from_utf8(script)?;
+    let args = dynamic_args();
+    let names = |kind: Candidates| -> Vec<&str> {
+        args.iter().filter(|(_, _, k)| *k == kind).map(|(name, _, _)| name.as_str()).collect()
+    };
+    Ok(match shell {
+        Shell::Bash => {
+            let cases = |kind: Candidates| -> String {
+                args.iter().filter(|(_, _, k)| *k == kind)
+                    .map(|(name, position, _)| format!("{},{}", name, position))
+                    .collect::<Vec<_>>().join("|")
+            };
+            let cli = Cli::command();
+            let commands: Vec<&str> = cli.get_subcommands()
+                .flat_map(|sub| iter::once(sub.get_name()).chain(sub.get_all_aliases()))
+                .collect();
+            let dynamic = BASH_DYNAMIC.replace("@COMMANDS@", &commands.join(" "))
+                .replace("@KEYS@", &cases(Candidates::Keys))
+                .replace("@HOSTS@", &cases(Candidates::Hosts));
+            let script = script.replace("complete -F _rjy ", "complete -F _rjy_with_sessions ");
+            match script.find("\nif [[ \"${BASH_VERSINFO") {
+                Some(at) => format!("{}{}{}", &script[..at], dynamic, &script[at..]),
+                None => return Err(anyhow!("Unexpected bash completion script from clap_complete."))
+            }
+        },
+        Shell::Zsh => {
+            // positional key and host arguments have no completion action;
+            // give them ours.
+            let lines: Vec<String> = script.lines().map(|line| {
+                let arg = line.trim_start_matches('\'').trim_start_matches(':');
+                let function = if arg.starts_with("key:") || arg.starts_with("key -- ") {
+                    "_rjy_keys"
+                } else if arg.starts_with("host:") || arg.starts_with("host -- ") {
+                    "_rjy_hosts"
+                } else {
+                    return line.to_string();
+                };
+                match line.strip_suffix("' \\") {
+                    Some(start) if line.starts_with('\'') && start.ends_with(':') => {
+                        format!("{}{}' \\", start, function)
+                    },
+                    _ => line.to_string()
+                }
+            }).collect();
+            let script = lines.join("\n") + "\n";
+            match script.split_once('\n') {
+                Some((compdef, rest)) => format!("{}\n{}{}", compdef, ZSH_DYNAMIC, rest),
+                None => return Err(anyhow!("Unexpected zsh completion script from clap_complete."))
+            }
+        },
+        Shell::Fish => {
+            let mut script = script;
+            for (kind, arg) in [(Candidates::Keys, "keys"), (Candidates::Hosts, "hosts")] {
+                script.push_str(&format!("complete -c rjy -n \"__fish_seen_subcommand_from {}\" -f \
+                                          -a \"(rjy __complete {} 2>/dev/null)\"\n",
+                                         names(kind).join(" "), arg));
+            }
+            script
+        },
+        _ => script
+    })
+}
+
+/// Write a man page for rjy, and one for each subcommand (e.g. rjy-dc.1),
+/// into `dir`, returning their paths.
+fn write_man_pages(dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)
+        .map_err(|err| anyhow!("Failed to create {:?}: {}", dir, err))?;
+    let mut cli = Cli::command().disable_help_subcommand(true);
+    cli.build();
+    let mut written = Vec::new();
+    write_man_page(&cli, dir, &mut written)?;
+    Ok(written)
+}
+
+fn write_man_page(cmd: &clap::Command, dir: &Path, written: &mut Vec<PathBuf>) -> Result<()> {
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        write_man_page(sub, dir, written)?;
+    }
+    // clap_mangen puts the after help under EXTRA; ours is examples, so give
+    // them their own section.
+    let examples = cmd.get_after_long_help().map(|text| text.to_string());
+    let man = clap_mangen::Man::new(cmd.clone().after_long_help(None::<&str>));
+    let path = dir.join(man.get_filename());
+    let mut page = Vec::new();
+    man.render(&mut page)?;
+    if let Some(examples) = examples {
+        let mut roff = clap_mangen::roff::Roff::new();
+        roff.control("SH", ["EXAMPLES"]);
+        roff.control("nf", []);
+        // skip the "Examples:" heading the --help output has.
+        for line in examples.lines().skip_while(|line| line.ends_with(':') && !line.starts_with(' ')) {
+            roff.text([clap_mangen::roff::roman(line)]);
+        }
+        roff.control("fi", []);
+        roff.to_writer(&mut page)?;
+    }
+    fs::write(&path, page)
+        .map_err(|err| anyhow!("Failed to write {:?}: {}", path, err))?;
+    written.push(path);
+    Ok(())
+}
+
+/// How long `--persist-ssh` waits for a new master connection to log in.
+const MASTER_WAIT_SECS: u64 = 30;
+
+/// How long a new tunnel gets to fail before `reconnect-loop` counts it as up.
+const RECONNECT_GRACE_SECS: u64 = 5;
+
+/// The most `reconnect-loop` will multiply its interval by after failures.
+const MAX_BACKOFF: u64 = 16;
+
+/// Keep one session's tunnel up, reconnecting whenever it goes down.
+fn reconnect_loop(key: &str, interval: u64, max_attempts: Option<u32>) -> Result<()> {
+    let mut failures = 0;
+    let mut backoff = 1;
+    let mut was_alive: Option<bool> = None;
+    // resolve a partial key or name once, rather than on every check.
+    let mut sessions = ConnectionCache::new();
+    sessions.load()?;
+    let key = &sessions.resolve_key(key)?;
+    loop {
+        // reload each time around, in case other rjy commands changed the cache.
+        let mut sessions = ConnectionCache::new();
+        sessions.load()?;
+        let key = sessions.resolve_key(key)?;
+        let conn = sessions.get_connection(&key)?;
+        if conn.is_local() {
+            return Err(anyhow!("Session {} is a local connection, with no SSH tunnel to keep up.", key));
+        }
+        conn.reap();
+        if conn.expired() {
+            if was_alive == Some(true) {
+                conn.run_on_disconnect()?;
+            }
+            let conn = sessions.get_connection_mut(&key)?;
+            if conn.tunnel_running() {
+                conn.kill_connection()?;
+                sessions.save()?;
+            }
+            println!("Session {} reached its --timeout-kill; not reconnecting it.", key);
+            return Ok(());
+        }
+        let alive = conn.is_alive();
+        match (was_alive, alive) {
+            (Some(true), false) => conn.run_on_disconnect()?,
+            (Some(false), true) => conn.run_on_reconnect()?,
+            _ => {}
+        }
+        was_alive = Some(alive);
+        if !alive {
+            println!("Session {} is down; reconnecting.", key);
+            sessions.get_connection_mut(&key)?.reconnect()?;
+            sessions.save()?;
+            thread::sleep(Duration::from_secs(RECONNECT_GRACE_SECS));
+            sessions.get_connection(&key)?.reap();
+            if sessions.get_connection(&key)?.is_alive() {
+                was_alive = Some(true);
+                AuditLogger::record("rc", sessions.get_connection(&key)?);
+                sessions.get_connection(&key)?.run_on_reconnect()?;
+                println!("Reconnected session {}.", key);
+                backoff = 1;
+            } else {
+                failures += 1;
+                println!("Could not reconnect session {} ({} failed attempts so far).", key, failures);
+                let persistent = sessions.get_connection(&key)?.persistent;
+                if !persistent && max_attempts.is_some_and(|max| failures >= max) {
+                    return Err(anyhow!("Giving up on session {} after {} failed attempts.", key, failures));
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+        thread::sleep(Duration::from_secs(interval * backoff));
+    }
+}
+
+fn print_plan(plan: &[PlanAction]) {
+    let count = |f: fn(&PlanAction) -> bool| plan.iter().filter(|a| f(a)).count();
+    for action in plan {
+        println!("{}", action.msg());
+    }
+    println!("Plan: {} to create, {} to update, {} to reconnect, {} to drop.",
+             count(|a| matches!(a, PlanAction::Create(..))),
+             count(|a| matches!(a, PlanAction::Update(..))),
+             count(|a| matches!(a, PlanAction::Reconnect(_))),
+             count(|a| matches!(a, PlanAction::Drop(_))));
+}
+
+// Examples for the subcommands' --help and man pages.
+const NEW_EXAMPLES: &str = "\
+Examples:
+
+  Add a session from the link Jupyter printed, tunnelling through ponderosa:
+  $ rjy new 'http://localhost:8906/lab?token=5e2f[...]8467' ponderosa
+
+  Paste the whole of Jupyter's console output instead, with a name and a tag:
+  $ rjy new - ponderosa --name analysis --tag gpu
+
+  Register a server reachable without a tunnel (e.g. over a VPN):
+  $ rjy new --local-only 'http://gpu-node-03:8888/lab?token=5e2f[...]8467'";
+
+const NEW_DETECT_EXAMPLES: &str = "\
+Examples:
+
+  Find the server running on ponderosa and add it:
+  $ rjy new-detect ponderosa
+
+  Pick one of several servers, with Jupyter from a conda env:
+  $ rjy new-detect ponderosa --port 8906 --jupyter ~/miniconda3/bin/jupyter";
+
+const NEW_RAW_EXAMPLES: &str = "\
+Examples:
+
+  Add a session from its port and token:
+  $ rjy new-raw ponderosa 8906 5e2f[...]8467";
+
+const LIST_EXAMPLES: &str = "\
+Examples:
+
+  List every session:
+  $ rjy list
+
+  Only the connected ones tagged gpu, most recently used first:
+  $ rjy list --tag gpu --alive-only --sort last-used
+
+  Choose the columns, or print JSON for scripts:
+  $ rjy list --fields key,status,link
+  $ rjy list --json";
+
+const DROP_EXAMPLES: &str = "\
+Examples:
+
+  Drop one session:
+  $ rjy drop ponderosa:8906
+
+  Pick the sessions to drop from a list:
+  $ rjy drop
+
+  Drop every session tagged scratch:
+  $ rjy drop --tag scratch";
+
+const RC_EXAMPLES: &str = "\
+Examples:
+
+  Reconnect one session, and open it in the browser:
+  $ rjy rc ponderosa:8906 --open
+
+  Reconnect every session with auto-reconnect on:
+  $ rjy rc";
+
+const DC_EXAMPLES: &str = "\
+Examples:
+
+  Disconnect one session:
+  $ rjy dc ponderosa:8906
+
+  Disconnect every session:
+  $ rjy dc --all
+
+  Disconnect every session tagged gpu:
+  $ rjy dc --tag gpu";
+
+const TAG_EXAMPLES: &str = "\
+Examples:
+
+  Add one tag and remove another:
+  $ rjy tag ponderosa:8906 +gpu -scratch";
+
+const GET_EXAMPLES: &str = "\
+Examples:
+
+  Open a session in another browser:
+  $ firefox \"$(rjy get analysis)\"
+
+  Print just its port:
+  $ rjy get analysis --format '{port}'";
+
+const OPEN_EXAMPLES: &str = "\
+Examples:
+
+  Open the session named analysis, reconnecting it first if it's down:
+  $ rjy open analysis --reconnect";
+
+const CURL_EXAMPLES: &str = "\
+Examples:
+
+  List a session's running kernels:
+  $ rjy curl analysis /api/kernels
+
+  Start a kernel:
+  $ rjy curl analysis /api/kernels -X POST --json -d '{\"name\": \"python3\"}'";
+
+const RUN_EXAMPLES: &str = "\
+Examples:
+
+  Run a script against a session's server:
+  $ rjy run analysis -- python script.py";
+
+const INFO_EXAMPLES: &str = "\
+Examples:
+
+  Show a session, token included:
+  $ rjy info analysis --show-token";
+
+const SHARE_EXAMPLES: &str = "\
+Examples:
+
+  Share a session for an hour:
+  $ rjy share analysis --duration-secs 3600";
+
+const NOTE_EXAMPLES: &str = "\
+Examples:
+
+  Note what a session is for, then clear it:
+  $ rjy note analysis 'fitting the big model'
+  $ rjy note analysis --clear";
+
+const MERGE_EXAMPLES: &str = "\
+Examples:
+
+  Add the sessions from a laptop's cache, keeping ours on conflicts:
+  $ rjy merge laptop-sessions --strategy local-wins";
+
+const APPLY_EXAMPLES: &str = "\
+Examples:
+
+  See what making the sessions match a file would do, then do it:
+  $ rjy apply sessions.yaml --dry-run
+  $ rjy apply sessions.yaml --prune";
+
+const HEALTHCHECK_EXAMPLES: &str = "\
+Examples:
+
+  Push the sessions' state to a Prometheus push gateway:
+  $ rjy healthcheck | curl --data-binary @- http://pushgateway:9091/metrics/job/rjy";
+
+const RECONNECT_LOOP_EXAMPLES: &str = "\
+Examples:
+
+  Keep a session up, checking every minute:
+  $ rjy reconnect-loop analysis --interval 60";
+
+#[derive(Parser)]
+#[clap(name = "rjy")]
+#[clap(about = "Manage remote Jupyter sessions through SSH tunnels.", long_about = INFO)]
+struct Cli {
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    debug: u8,
+
+    /// Whether to colour output (auto follows NO_COLOR and CLICOLOR, and
+    /// never colours into a pipe).
+    #[arg(long, value_enum, global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Don't colour output (the same as --color never).
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+impl Cli {
+    /// Whether to colour output, going by --no-color and --color (and, for
+    /// --color auto, the terminal, NO_COLOR, and CLICOLOR).
+    fn should_colorize(&self) -> bool {
+        !self.no_color && self.color.enabled()
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Add a session from a Jupyter link.
+    #[command(visible_alias = "add", after_long_help = NEW_EXAMPLES)]
+    New {
+        /// The Jupyter link, or the console output Jupyter printed it in ('-'
+        /// to read it from stdin).
+        link: Option<String>,
+        /// The host to SSH to (defaults to the link's hostname, if it isn't localhost).
+        host: Option<String>,
+        /// The host to SSH to, e.g. with --port and --token in place of a link.
+        #[arg(long = "host", id = "host_flag", value_name = "HOST", conflicts_with = "host")]
+        host_flag: Option<String>,
+        /// Which server's link to use, if the pasted text has links for several
+        /// (or, without a link, the server's port).
+        #[arg(long)]
+        port: Option<u16>,
+        /// The server's token, in place of a link or to replace the link's
+        /// (e.g. after the server restarted with a known token).
+        #[arg(long)]
+        token: Option<String>,
+        /// Read the link from this file (then the only argument is the host).
+        #[arg(long, conflicts_with_all = ["link_env", "from_clipboard"])]
+        link_file: Option<PathBuf>,
+        /// Read the link from this environment variable (then the only
+        /// argument is the host).
+        #[arg(long, value_name = "VAR", conflicts_with = "from_clipboard")]
+        link_env: Option<String>,
+        /// Read the link (or Jupyter's output) from the clipboard (then the
+        /// only argument is the host).
+        #[arg(long)]
+        from_clipboard: bool,
+        /// Start the server by running this command on the remote host, and
+        /// use the link it prints (then the only argument is the host), e.g.
+        /// "jupyter lab --no-browser".
+        #[arg(long, value_name = "COMMAND",
+              conflicts_with_all = ["link_file", "link_env", "from_clipboard", "token"])]
+        remote_cmd: Option<String>,
+        #[command(flatten)]
+        open: BrowserOptions,
+        #[command(flatten)]
+        options: NewOptions
+    },
+    /// Add a session for a Jupyter server found running on the remote host,
+    /// rather than from a link.
+    #[command(after_long_help = NEW_DETECT_EXAMPLES)]
+    NewDetect {
+        /// The host to SSH to (e.g. one in ~/.ssh/config).
+        host: String,
+        /// Which server to use, if several are running.
+        #[arg(long)]
+        port: Option<u16>,
+        /// How to run Jupyter on the remote host, e.g. a path in a conda env.
+        #[arg(long = "jupyter", value_name = "COMMAND", default_value = "jupyter")]
+        remote_jupyter: String,
+        #[command(flatten)]
+        options: NewOptions
+    },
+    /// Add a session from its port and token, rather than a link.
+    #[command(after_long_help = NEW_RAW_EXAMPLES)]
+    NewRaw {
+        /// The host to SSH to (e.g. one in ~/.ssh/config).
+        host: String,
+        port: u16,
+        token: String,
+        /// The link's scheme.
+        #[arg(long, default_value = "http")]
+        scheme: String,
+        #[command(flatten)]
+        options: NewOptions
+    },
+    /// List sessions and whether they're connected.
+    #[command(visible_alias = "ls", after_long_help = LIST_EXAMPLES)]
+    List {
+        #[command(flatten)]
+        filter: SessionFilter,
+        #[command(flatten)]
+        order: SortOrder,
+        /// Show more about each session in the table (the remote host's
+        /// full name, if known).
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print each session as a block of lines with everything about it,
+        /// tokens left out.
+        #[arg(short, long, conflicts_with_all = ["output", "format", "json", "porcelain", "quiet", "fields"])]
+        long: bool,
+        /// With --long, show the tokens.
+        #[arg(long, requires = "long")]
+        show_tokens: bool,
+        /// The table's columns, in order, e.g. key,status,age,local-port,note
+        /// (by default, the config file's list_fields). Fields: key, status,
+        /// pid, auto, age, host, port, local-port, link, tags, note, remote-host.
+        #[arg(long, value_delimiter = ',', conflicts_with_all = ["format", "json", "porcelain", "quiet"])]
+        fields: Option<Vec<String>>,
+        /// Show links and notes in full, rather than shortening them to fit the
+        /// table in the terminal (they're never shortened into a pipe).
+        #[arg(long)]
+        full: bool,
+        /// Make the table's links clickable, opening the local URL, even if the
+        /// terminal isn't known to support OSC 8 hyperlinks (never into a pipe).
+        #[arg(long)]
+        hyperlinks: bool,
+        /// Print just the keys, one per line (nothing if there are none).
+        #[arg(short, long, conflicts_with_all = ["output", "format", "json", "porcelain"])]
+        quiet: bool,
+        /// How to print the sessions.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+        /// Print each session as this template, e.g. '{key}\t{status}\t{local_url}'.
+        /// Fields: key, name, host, port, local_port, status, pid, link,
+        /// local_url, created_at, tags, note.
+        #[arg(long, conflicts_with = "output")]
+        format: Option<String>,
+        /// Print the sessions as JSON (short for --output json).
+        #[arg(long, conflicts_with_all = ["output", "format"])]
+        json: bool,
+        /// Print one tab-separated line per session, in a format that won't
+        /// change within a version (the default, and only, version is v1).
+        #[arg(long, value_enum, value_name = "VERSION", num_args = 0..=1,
+              default_missing_value = "v1", require_equals = true,
+              conflicts_with_all = ["output", "format", "json"])]
+        porcelain: Option<PorcelainVersion>
+    },
+    /// Kill a session's Jupyter server on the remote host, then drop the
+    /// session.
+    KillRemote {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// The signal to send, e.g. INT or 9.
+        #[arg(short, long, default_value = "TERM")]
+        signal: String,
+        /// Also drop the session (and its tunnel).
+        #[arg(long, value_enum, default_value_t = Switch::On)]
+        drop_local: Switch
+    },
+    /// Disconnect sessions and forget them.
+    #[command(visible_alias = "rm", after_long_help = DROP_EXAMPLES)]
+    Drop {
+        /// The session to drop (or give --all or --tag; without any, pick
+        /// sessions from a list).
+        key: Option<String>,
+        /// Drop every session.
+        #[arg(long, conflicts_with = "key")]
+        all: bool,
+        /// Drop all sessions with this tag.
+        #[arg(long, conflicts_with_all = ["key", "all"])]
+        tag: Option<String>,
+        /// Include pinned sessions, without asking.
+        #[arg(long)]
+        include_pinned: bool
+    },
+    /// Reconnect a session (by default, every session with auto-reconnect on).
+    #[command(after_long_help = RC_EXAMPLES)]
+    Rc {
+        /// The session to reconnect.
+        key: Option<String>,
+        /// Reconnect all sessions with this tag.
+        #[arg(long, conflicts_with = "key")]
+        tag: Option<String>,
+        #[command(flatten)]
+        open: BrowserOptions
+    },
+    /// Disconnect sessions, keeping them to reconnect later.
+    #[command(after_long_help = DC_EXAMPLES)]
+    Dc {
+        /// The session to disconnect (or give --all or --tag; without any,
+        /// pick sessions from a list).
+        key: Option<String>,
+        /// Disconnect every session.
+        #[arg(long, conflicts_with = "key")]
+        all: bool,
+        /// Disconnect all sessions with this tag.
+        #[arg(long, conflicts_with_all = ["key", "all"])]
+        tag: Option<String>,
+        /// Include pinned sessions when disconnecting several.
+        #[arg(long)]
+        include_pinned: bool
+    },
+    /// List session names and the keys they stand for.
+    Aliases {
+    },
+    /// Show the log of sessions created, dropped, disconnected, and reconnected.
+    AuditLog {
+        /// Show only the last N entries.
+        #[arg(short = 'n', long)]
+        lines: Option<usize>,
+        /// Show only the entries for this session.
+        key: Option<String>
+    },
+    /// Show or set whether 'rjy rc' without a key reconnects a session.
+    Auto {
+        /// The session, by key (host:port) or name.
+        key: String,
+        #[arg(value_enum)]
+        state: Option<Switch>
+    },
+    /// Move the tokens of all sessions from the session cache to the OS
+    /// keychain.
+    KeychainMigrate,
+    /// Work with the config file (~/.config/rjy/config.toml).
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Print a script that makes a shell tab-complete rjy's commands and flags
+    ///
+    /// In bash, zsh and fish it also completes session keys and hosts.
+    ///
+    /// Save it where your shell looks for completions, e.g.:
+    ///
+    ///   bash: rjy completions bash > ~/.local/share/bash-completion/completions/rjy
+    ///   zsh:  rjy completions zsh > ~/.zfunc/_rjy
+    ///         (with 'fpath+=~/.zfunc' before 'compinit' in ~/.zshrc)
+    ///   fish: rjy completions fish > ~/.config/fish/completions/rjy.fish
+    ///
+    /// then start a new shell.
+    #[command(verbatim_doc_comment)]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell
+    },
+    /// Show or set whether 'rjy reconnect-loop' keeps reconnecting a session
+    /// however often it fails.
+    #[command(visible_alias = "set-persistent")]
+    Persistent {
+        /// The session, by key (host:port) or name.
+        key: String,
+        #[arg(value_enum)]
+        state: Option<Switch>
+    },
+    /// Pin a session, so bulk operations leave it alone.
+    Pin {
+        /// The session, by key (host:port) or name.
+        key: String
+    },
+    /// Unpin a session.
+    Unpin {
+        /// The session, by key (host:port) or name.
+        key: String
+    },
+    /// Show or edit a session's tags: '+tag' adds a tag, '-tag' removes one.
+    #[command(after_long_help = TAG_EXAMPLES)]
+    Tag {
+        /// The session, by key (host:port) or name.
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        changes: Vec<String>
+    },
+    /// Print a session's local URL, token included, and nothing else (e.g.
+    /// for scripts).
+    #[command(after_long_help = GET_EXAMPLES)]
+    Get {
+        /// The session (by default, the config file's default_session).
+        key: Option<String>,
+        /// Fail if the session's tunnel is down.
+        #[arg(long)]
+        check: bool,
+        /// Print this template instead of the URL (see `rjy list --format`).
+        #[arg(long)]
+        format: Option<String>
+    },
+    /// Print a session's token (connected or not), and nothing else.
+    Token {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Print it as an HTTP header, e.g. for 'curl -H'.
+        #[arg(long, conflicts_with = "export")]
+        header: bool,
+        /// Print it as a shell command setting JUPYTER_TOKEN, for eval.
+        #[arg(long)]
+        export: bool
+    },
+    /// Copy a session's local URL to the clipboard (through the terminal
+    /// with OSC 52 if there's no clipboard here, e.g. over SSH).
+    Copy {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Copy just the token.
+        #[arg(long)]
+        token_only: bool
+    },
+    /// Open a session in the browser (by default, the config file's
+    /// default_session, or else the one last opened).
+    #[command(after_long_help = OPEN_EXAMPLES)]
+    Open {
+        /// The session to open.
+        key: Option<String>,
+        /// Reconnect the session first if it's down.
+        #[arg(long)]
+        reconnect: bool
+    },
+    /// Make a request to a session's Jupyter API with its token, e.g.
+    /// 'rjy curl <key> /api/kernels'.
+    #[command(after_long_help = CURL_EXAMPLES)]
+    Curl {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// The API path, under the server's base URL.
+        path: String,
+        /// The HTTP method (GET, or POST with --data).
+        #[arg(short = 'X', long = "request", value_name = "METHOD")]
+        method: Option<String>,
+        /// The JSON request body, or @file to read it from a file.
+        #[arg(short = 'd', long)]
+        data: Option<String>,
+        /// Pretty-print a JSON response.
+        #[arg(long, conflicts_with = "exec_curl")]
+        json: bool,
+        /// Run curl to make the request instead (e.g. for HTTPS servers).
+        #[arg(long)]
+        exec_curl: bool
+    },
+    /// Run a command with JUPYTER_URL, JUPYTER_TOKEN, and JUPYTER_PORT set for a session.
+    #[command(after_long_help = RUN_EXAMPLES)]
+    Run {
+        /// The session, by key (host:port) or name.
+        key: String,
+        #[arg(last = true, required = true)]
+        command: Vec<String>
+    },
+    /// Show everything known about a session, including how it was created.
+    #[command(after_long_help = INFO_EXAMPLES)]
+    Info {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Also show the token, as stored and URL-encoded.
+        #[arg(long)]
+        show_token: bool,
+        /// Print the session as this template instead (see `rjy list --format`).
+        #[arg(long, conflicts_with = "show_token")]
+        format: Option<String>
+    },
+    /// Check a session's tunnel and server step by step, suggesting fixes
+    /// for whatever's broken.
+    Diagnose {
+        /// The session, by key (host:port) or name.
+        key: String
+    },
+    /// Change a session's name, or its key (e.g. after its host's alias in
+    /// ~/.ssh/config changes), without touching its tunnel.
+    Rename {
+        /// The session's current name, or its key.
+        old: String,
+        /// The new name, or the new key (host:port, with the same port).
+        new: String
+    },
+    /// Show, set, or clear the note on a session.
+    #[command(visible_alias = "relabel", after_long_help = NOTE_EXAMPLES)]
+    Note {
+        /// The session, by key (host:port) or name.
+        key: String,
+        text: Option<String>,
+        /// Remove the note.
+        #[arg(long, conflicts_with = "text")]
+        clear: bool
+    },
+    /// Print a URL (and QR code) for a colleague to reach a session through
+    /// this machine, closing the tunnel after a while if asked.
+    #[command(after_long_help = SHARE_EXAMPLES)]
+    Share {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Close the session's tunnel after this many seconds.
+        #[arg(long, value_name = "SECS")]
+        duration_secs: Option<u64>
+    },
+    /// Print a QR code of a session's URL, to open it on a phone or tablet
+    /// on the same network (the tunnel must listen beyond localhost).
+    Qr {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Draw the code with '#'s, for terminals or fonts without
+        /// half-block characters.
+        #[arg(long)]
+        ascii: bool
+    },
+    /// Print a 'rjy new' command that would recreate a session.
+    ShowCmd {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Include the token rather than a placeholder.
+        #[arg(long)]
+        with_token: bool
+    },
+    /// Rewrite the list of session keys used for shell completion.
+    RefreshCompletions {
+    },
+    /// Write man pages for rjy and its subcommands into a directory (e.g.
+    /// for packaging).
+    #[command(hide = true)]
+    Mangen {
+        dir: PathBuf
+    },
+
+    /// Report whether each session is up, for monitoring (e.g. with Prometheus).
+    #[command(after_long_help = HEALTHCHECK_EXAMPLES)]
+    Healthcheck {
+        #[arg(long, value_enum, default_value_t = HealthcheckFormat::Prometheus)]
+        format: HealthcheckFormat
+    },
+    /// Keep one session connected, reconnecting it whenever it goes down.
+    #[command(after_long_help = RECONNECT_LOOP_EXAMPLES)]
+    ReconnectLoop {
+        /// The session, by key (host:port) or name.
+        key: String,
+        /// Seconds between checks.
+        #[arg(long, default_value_t = 30)]
+        interval: u64,
+        /// Give up after this many failed reconnects in total (unless the
+        /// session is persistent).
+        #[arg(long)]
+        max_attempts: Option<u32>
+    },
+    /// Add the sessions from another session cache (e.g. one copied from
+    /// another machine).
+    #[command(after_long_help = MERGE_EXAMPLES)]
+    Merge {
+        file: PathBuf,
+        /// How to settle keys both caches have with different sessions.
+        #[arg(long, value_enum, default_value_t = MergeStrategy::LocalWins)]
+        strategy: MergeStrategy
+    },
+    /// Create, reconnect, or drop sessions to match a YAML sessions file.
+    #[command(after_long_help = APPLY_EXAMPLES)]
+    Apply {
+        file: PathBuf,
+        /// Drop cached sessions that are not in the sessions file.
+        #[arg(long)]
+        prune: bool,
+        /// Show the plan without acting on it.
+        #[arg(long)]
+        dry_run: bool,
+        /// Let --prune drop pinned sessions.
+        #[arg(long)]
+        include_pinned: bool
+    }
+}
+
+/// Run rjy with the process's arguments.
+pub fn run() -> Result<()> {
+    // 'rjy __complete keys|hosts', for the completion scripts. It isn't a
+    // (hidden) subcommand, as clap_complete's bash script reads '__' in a
+    // subcommand's name as nesting.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [command, kind] = args.as_slice() {
+        if command == "__complete" {
+            if let Ok(kind) = Candidates::from_str(kind, false) {
+                print_candidates(kind);
+            }
+            return Ok(());
+        }
+    }
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Commands::New { link, host, host_flag, port, token, link_file, link_env, from_clipboard,
+                             remote_cmd, open, options }) => {
+            let host = host.as_deref().or(host_flag.as_deref());
+            if token.is_some() {
+                eprintln!("{}", HISTORY_NOTE);
+            }
+            let from_parts = link.is_none() && link_file.is_none() && link_env.is_none()
+                && !*from_clipboard && (host_flag.is_some() || token.is_some());
+            let mut remote_pid = None;
+            let (link, host) = match (remote_cmd, from_parts) {
+                (Some(cmd), _) => {
+                    let Some(host) = host.or(link.as_deref()) else {
+                        return Err(anyhow!("Give the host to run --remote-cmd on."));
+                    };
+                    let (link, pid) = Connection::launch_and_capture_url(host, cmd)?;
+                    remote_pid = Some(pid);
+                    (link, Some(host.to_string()))
+                },
+                (None, true) => (link_from_parts(host, *port, token.as_deref(), options.allow_empty_token)?,
+                                 host.map(|host| host.to_string())),
+                (None, false) => read_link(link.as_deref(), host, link_file.as_ref(),
+                                           link_env.as_deref(), *from_clipboard)?
+            };
+            let mut found = find_link(&link, *port)?;
+            if let Some(token) = token.as_deref().filter(|_| !from_parts) {
+                found.link = replace_token(&found.link, token)?;
+            }
+            let host = match options.local_only {
+                true => found.parts.ssh_host(host.as_deref()).unwrap_or_else(|_| "localhost".to_string()),
+                false => found.parts.ssh_host(host.as_deref())?
+            };
+            let mut options = options.clone();
+            options.root_dir = options.root_dir.or(found.root_dir);
+            options.auto_label |= Config::load()?.auto_label;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = sessions.new_connection(&found.link, &host, &options)?;
+            if remote_pid.is_some() {
+                let conn = sessions.get_connection_mut(&key)?;
+                conn.remote_cmd = remote_cmd.clone();
+                conn.remote_jupyter_pid = remote_pid;
+            }
+            sessions.save()?;
+            if open.wanted()? {
+                sessions.open_when_up(&key)?;
+                sessions.save()?;
+            }
+            Ok(())
+        },
+        Some(Commands::NewDetect { host, port, remote_jupyter, options }) => {
+            let found = find_link(&detect_servers(host, remote_jupyter)?, *port)?;
+            let mut options = options.clone();
+            options.root_dir = options.root_dir.or(found.root_dir);
+            options.auto_label |= Config::load()?.auto_label;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.new_connection(&found.link, host, &options)?;
+            sessions.save()
+        },
+        Some(Commands::NewRaw { host, port, token, scheme, options }) => {
+            if !token.is_empty() {
+                eprintln!("{}", HISTORY_NOTE);
+            }
+            let link = raw_link(scheme, *port, token)?;
+            let mut options = options.clone();
+            options.auto_label |= Config::load()?.auto_label;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.new_connection(&link, host, &options)?;
+            sessions.save()
+        },
+        Some(Commands::List { filter, order, verbose, long, show_tokens, fields, full, hyperlinks,
+                              quiet, output, format, json, porcelain }) => {
+            let template = format.as_deref().map(Template::parse).transpose()?;
+            let output = &match (quiet, json, porcelain) {
+                _ if *long => OutputFormat::Long { show_tokens: *show_tokens },
+                (true, _, _) => OutputFormat::Keys,
+                (_, true, _) => OutputFormat::Json,
+                (_, _, Some(version)) => OutputFormat::Porcelain(*version),
+                _ => *output
+            };
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let mut fields = match fields {
+                Some(fields) => parse_list_fields(fields)?,
+                None => match Config::load()?.list_fields {
+                    Some(fields) => parse_list_fields(&fields)
+                        .map_err(|err| anyhow!("In list_fields in {:?}: {}", Config::path().unwrap_or_default(), err))?,
+                    None => {
+                        let mut fields: Vec<String> = DEFAULT_LIST_FIELDS.iter().map(|field| field.to_string()).collect();
+                        if sessions.connections.values().any(|conn| conn.alias.is_some()) {
+                            fields.insert(0, "name".to_string());
+                        }
+                        fields
+                    }
+                }
+            };
+            if *verbose && !fields.iter().any(|field| field == "remote-host") {
+                fields.insert(1.min(fields.len()), "remote-host".to_string());
+            }
+            let layout = TableLayout {
+                fields,
+                width: if *full { None } else { terminal_width() },
+                colour: cli.should_colorize() && *output != OutputFormat::TableNoColor,
+                hyperlinks: io::stdout().is_terminal() && (*hyperlinks || supports_hyperlinks())
+            };
+            sessions.list(&mut io::stdout(), filter, order, *output, template.as_ref(), &layout)?;
+            Ok(())
+        },
+        Some(Commands::Rc { key, tag, open }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let keys = match (key, tag) {
+                (_, Some(tag)) => {
+                    let keys = sessions.keys_with_tag(tag)?;
+                    for k in keys.iter() {
+                        sessions.reconnect(k)?;
+                    }
+                    keys
+                },
+                (None, None) => sessions.reconnect_all()?,
+                (Some(k), None) => {
+                    let k = sessions.resolve_key(k)?;
+                    sessions.reconnect(&k)?;
+                    vec![k]
+                }
+            };
+            // save first, so the new tunnels are kept whatever happens.
+            sessions.save()?;
+            if open.wanted()? {
+                for k in keys.iter() {
+                    sessions.open_when_up(k)?;
+                }
+                sessions.save()?;
+            }
+            Ok(())
+        },
+        Some(Commands::Dc { key, all, tag, include_pinned }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            match (key, tag) {
+                (_, Some(tag)) => {
+                    let keys = sessions.keys_with_tag(tag)?;
+                    for k in sessions.bulk_keys(keys, *include_pinned) {
+                        sessions.disconnect(&k)?;
+                    }
+                },
+                (None, None) if *all => sessions.disconnect_all(*include_pinned)?,
+                (None, None) => {
+                    for k in sessions.pick_sessions("disconnect")? {
+                        sessions.disconnect(&k)?;
+                    }
+                },
+                (Some(k), None) => {
+                    let k = sessions.resolve_key(k)?;
+                    sessions.disconnect(&k)?
+                }
+            }
+            Ok(())
+        },
+        Some(Commands::Tag { key, changes }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            if changes.is_empty() {
+                println!("{}", sessions.get_connection(key)?.tags.join("\n"));
+                return Ok(());
+            }
+            sessions.edit_tags(key, changes)?;
+            sessions.save()
+        },
+        Some(Commands::KillRemote { key, signal, drop_local }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.kill_remote(key, signal, *drop_local == Switch::On)?;
+            sessions.save()
+        },
+        Some(Commands::Drop { key, all, tag, include_pinned }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            if let Some(tag) = tag {
+                let keys = sessions.keys_with_tag(tag)?;
+                for k in sessions.bulk_keys(keys, *include_pinned) {
+                    sessions.drop_connection(&k)?;
+                }
+            } else if *all {
+                sessions.drop_all_connections(*include_pinned)?;
+            } else {
+                let keys = match key {
+                    None => sessions.pick_sessions("drop")?,
+                    Some(k) => vec![sessions.resolve_key(k)?]
+                };
+                for k in &keys {
+                    let pinned = sessions.get_connection(k)?.pinned;
+                    if pinned && !*include_pinned
+                        && !confirm(&format!("Session {} is pinned. Drop it anyway?", k))? {
+                        println!("Kept session {}.", k);
+                        continue;
+                    }
+                    sessions.drop_connection(k)?;
+                }
+            }
+            sessions.save()
+        },
+        Some(Commands::AuditLog { lines, key }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            // Dropped sessions can still be looked up by their key.
+            let key = key.as_ref().map(|key| sessions.resolve_key(key).unwrap_or(key.clone()));
+            AuditLogger::new()?.show(*lines, key.as_deref(), cli.should_colorize())
+        },
+        Some(Commands::Aliases { }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.aliases();
+            Ok(())
+        },
+        Some(Commands::Auto { key, state }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            match state {
+                None => {
+                    let auto = sessions.get_connection(key)?.auto;
+                    println!("{}", if auto { "on" } else { "off" });
+                    Ok(())
+                },
+                Some(state) => {
+                    sessions.set_auto(key, *state == Switch::On)?;
+                    sessions.save()
+                }
+            }
+        },
+        Some(Commands::Persistent { key, state }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            match state {
+                None => {
+                    let persistent = sessions.get_connection(key)?.persistent;
+                    println!("{}", if persistent { "on" } else { "off" });
+                    Ok(())
+                },
+                Some(state) => {
+                    sessions.set_persistent(key, *state == Switch::On)?;
+                    sessions.save()
+                }
+            }
+        },
+        Some(Commands::Pin { key }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.set_pinned(key, true)?;
+            sessions.save()
+        },
+        Some(Commands::Unpin { key }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.set_pinned(key, false)?;
+            sessions.save()
+        },
+        Some(Commands::Get { key, check, format }) => {
+            let template = format.as_deref().map(Template::parse).transpose()?;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &match key {
+                Some(key) => sessions.resolve_key(key)?,
+                None => sessions.default_key()?.ok_or_else(|| {
+                    anyhow!("No key given, and the config file sets no default_session.")
+                })?
+            };
+            let conn = sessions.get_connection(key)?;
+            if *check && !conn.is_alive() {
+                return Err(anyhow!("Session {} is disconnected.", key));
+            }
+            match template {
+                Some(template) => println!("{}", template.render(conn)),
+                None => println!("{}", conn.local_url())
+            }
+            Ok(())
+        },
+        Some(Commands::Token { key, header, export }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            let token = &sessions.get_connection(key)?.get_token()?;
+            match (header, export) {
+                (true, _) => println!("Authorization: token {}", token),
+                (_, true) => println!("export JUPYTER_TOKEN={}", shell_quote(token)),
+                _ => println!("{}", token)
+            }
+            Ok(())
+        },
+        Some(Commands::Copy { key, token_only }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            let conn = sessions.get_connection(key)?;
+            let (what, text) = match token_only {
+                true => ("token", conn.get_token()?),
+                false => ("local URL", conn.local_url())
+            };
+            let used = write_clipboard(&text)?;
+            eprintln!("Copied the {} of {} to the clipboard with {}.", what, key, used);
+            Ok(())
+        },
+        Some(Commands::Open { key, reconnect }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &match key {
+                Some(key) => sessions.resolve_key(key)?,
+                None => match sessions.default_key()? {
+                    Some(key) => key,
+                    None => sessions.most_recent_key()?
+                }
+            };
+            // save even if the browser failed, to keep a reconnected tunnel.
+            let opened = sessions.open(key, *reconnect);
+            sessions.save()?;
+            opened
+        },
+        Some(Commands::Curl { key, path, method, data, json, exec_curl }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.api_request(key, path, method.as_deref(), data.as_deref(), *json, *exec_curl)
+        },
+        Some(Commands::Run { key, command }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.run_command(key, command)
+        },
+        Some(Commands::Info { key, show_token, format }) => {
+            let template = format.as_deref().map(Template::parse).transpose()?;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            match template {
+                Some(template) => {
+                    println!("{}", template.render(sessions.get_connection(key)?));
+                    Ok(())
+                },
+                None => sessions.info(key, *show_token)
+            }
+        },
+        Some(Commands::Config(ConfigCommands::Validate)) => Config::validate(),
+        Some(Commands::Completions { shell }) => {
+            print!("{}", completion_script(*shell)?);
+            Ok(())
+        },
+
+        Some(Commands::KeychainMigrate) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            // save whatever was moved before any failure.
+            let migrated = sessions.keychain_migrate();
+            sessions.save()?;
+            migrated
+        },
+        Some(Commands::Diagnose { key }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.diagnose(key)
+        },
+        Some(Commands::Rename { old, new }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.rename(old, new)?;
+            sessions.save()
+        },
+        Some(Commands::Note { key, text, clear }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            match (text, clear) {
+                (None, false) => {
+                    let note = sessions.get_connection(key)?.note.clone();
+                    println!("{}", note.unwrap_or_default());
+                    return Ok(());
+                },
+                (Some(text), false) if !text.is_empty() => sessions.set_note(key, Some(text.clone()))?,
+                _ => sessions.set_note(key, None)?
+            }
+            sessions.save()
+        },
+        Some(Commands::Share { key, duration_secs }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.share(key, *duration_secs)?;
+            sessions.save()
+        },
+        Some(Commands::Qr { key, ascii }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            sessions.qr(key, *ascii)
+        },
+        Some(Commands::ShowCmd { key, with_token }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let key = &sessions.resolve_key(key)?;
+            println!("{}", sessions.get_connection(key)?.show_cmd(*with_token));
+            Ok(())
+        },
+        Some(Commands::RefreshCompletions { }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.write_completion_keys()?;
+            println!("Wrote the completion keys to {:?}.", data_dir()?.join(COMPLETION_KEYS));
+            Ok(())
+        },
+        Some(Commands::Mangen { dir }) => {
+            let written = write_man_pages(dir)?;
+            println!("Wrote {} man pages to {:?}.", written.len(), dir);
+            Ok(())
+        },
+        Some(Commands::Healthcheck { format }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            sessions.healthcheck(*format)
+        },
+        Some(Commands::ReconnectLoop { key, interval, max_attempts }) => {
+            reconnect_loop(key, *interval, *max_attempts)
+        },
+        Some(Commands::Merge { file, strategy }) => {
+            let other = ConnectionCache::load_file(file)?;
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let mut conflicts = sessions.merge(other);
+            for conflict in conflicts.iter_mut() {
+                conflict.resolution = match strategy {
+                    MergeStrategy::LocalWins => MergeResolution::KeepLocal,
+                    MergeStrategy::RemoteWins => MergeResolution::KeepRemote,
+                    MergeStrategy::Interactive => {
+                        conflict.ask()?;
+                        conflict.resolution
+                    }
+                };
+            }
+            sessions.resolve_conflicts(conflicts)?;
+            sessions.save()
+        },
+        Some(Commands::Apply { file, prune, dry_run, include_pinned }) => {
+            let mut sessions = ConnectionCache::new();
+            sessions.load()?;
+            let specs = SessionSpec::load(file)?;
+            let plan = sessions.plan(&specs, *prune, *include_pinned)?;
+            print_plan(&plan);
+            if *dry_run {
+                return Ok(());
+            }
+            sessions.apply(&plan)?;
+            sessions.save()
+        },
+        None => {
+            println!("{}\n", INFO);
+            std::process::exit(1);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "5e2f0c7d9b1a48e6a3f2c1d0e9b8a7f6c5d4e3f2a1b0c9d8";
+
+    /// The error `UrlParts::parse` gives for `link`, failing if it parses.
+    fn parse_error(link: &str) -> String {
+        match UrlParts::parse(link) {
+            Ok(_) => panic!("{:?} parsed, but shouldn't have", link),
+            Err(err) => err.to_string()
+        }
+    }
+
+    #[test]
+    fn malformed_links_give_helpful_errors() {
+        let cases = [
+            // missing scheme
+            (format!("localhost:8888/lab?token={}", TOKEN), "not an http:// or https:// URL"),
+            (format!("//localhost:8888/lab?token={}", TOKEN), "cannot parse it as a URL"),
+            ("ponderosa".to_string(), "cannot parse it as a URL"),
+            (String::new(), "cannot parse it as a URL"),
+            (format!("ftp://localhost:8888/?token={}", TOKEN), "not an http:// or https:// URL"),
+            // malformed port
+            (format!("http://localhost:88a8/lab?token={}", TOKEN), "invalid port number"),
+            (format!("http://localhost:99999/lab?token={}", TOKEN), "invalid port number"),
+            // embedded whitespace, e.g. from a wrapped terminal line
+            (format!("http://localhost:8888/lab?token={} ", TOKEN), "contains whitespace"),
+            (format!("http://localhost:8888/lab?to\nken={}", TOKEN), "contains whitespace"),
+            (format!("http://localhost:8888/lab?token={}\n{}", &TOKEN[..8], &TOKEN[8..]), "contains whitespace"),
+            (format!("http://localhost:8888/lab?token={}\tfoo", TOKEN), "contains whitespace"),
+            // trailing junk after the host or port
+            (format!("http://localhost:8888junk/lab?token={}", TOKEN), "invalid port number"),
+            (format!("http://localhost:8888:9999/lab?token={}", TOKEN), "invalid port number"),
+            ("http://".to_string(), "cannot parse it as a URL")
+        ];
+        for (link, problem) in cases.iter() {
+            let err = parse_error(link);
+            assert!(err.contains(problem), "{:?}: expected {:?} in:\n{}", link, problem, err);
+            assert!(err.contains("Expected: a link like http://localhost:8888/lab?token=<token>"),
+                    "{:?}: no example in:\n{}", link, err);
+            assert!(err.contains("quote it"), "{:?}: no quoting advice in:\n{}", link, err);
+            assert!(!err.contains(TOKEN), "{:?}: token shown in:\n{}", link, err);
+        }
+    }
+
+    #[test]
+    fn links_without_a_port_or_token_are_caught_later() {
+        // a link without a port is one through a proxy, so it parses, but
+        // the port to forward has to be given.
+        let parts = UrlParts::parse(&format!("https://hub.example.edu/user/me/lab?token={}", TOKEN)).unwrap();
+        let err = parts.tunnel_port(None).unwrap_err().to_string();
+        assert!(err.contains("The link has no port") && err.contains("--remote-port"), "{}", err);
+        assert_eq!(parts.tunnel_port(Some(8888)).unwrap(), 8888);
+
+        // servers using password authentication have no token, so a link
+        // without one parses; without a link, the missing token is an error.
+        assert_eq!(UrlParts::parse("http://localhost:8888/lab").unwrap().token, None);
+        assert_eq!(UrlParts::parse("http://localhost:8888/lab?token=").unwrap().token, None);
+        let err = link_from_parts(Some("ponderosa"), Some(8888), None, false).unwrap_err().to_string();
+        assert!(err.contains("No token given") && err.contains("--allow-empty-token"), "{}", err);
+    }
+
+    /// A session for `link` on ponderosa, without starting its tunnel.
+    fn session(link: &str) -> Connection {
+        session_with(link, "ponderosa", &NewOptions::default())
+    }
+
+    fn session_with(link: &str, host: &str, options: &NewOptions) -> Connection {
+        Connection::from_link(link, host, options).unwrap()
+    }
+
+    #[test]
+    fn base_url_is_kept_in_local_urls() {
+        // link path, base_url, local URL path
+        let cases = [
+            ("/lab", "/", "/lab"),
+            ("/tree", "/", "/tree"),
+            ("/", "/", "/"),
+            ("/jupyter/lab", "/jupyter/", "/jupyter/lab"),
+            ("/jupyter/tree", "/jupyter/", "/jupyter/tree"),
+            ("/jupyter/", "/jupyter/", "/jupyter/"),
+            ("/user/vince/lab", "/user/vince/", "/user/vince/lab")
+        ];
+        for (path, base_url, local_path) in cases {
+            let conn = session(&format!("http://localhost:8888{}?token={}", path, TOKEN));
+            assert_eq!(conn.base_url(), base_url, "{}", path);
+            assert_eq!(conn.server_url(), format!("http://localhost:8888{}", base_url.trim_end_matches('/')));
+            assert_eq!(conn.local_url(), format!("http://localhost:8888{}?token={}", local_path, TOKEN));
+        }
+        assert_eq!(split_base_url("/jupyter/api/sessions"), ("/jupyter/".to_string(), "api/sessions".to_string()));
+    }
+
+    #[test]
+    fn base_url_is_kept_when_the_port_is_remapped() {
+        let options = NewOptions { remote_port: Some(9999), ..NewOptions::default() };
+        let conn = session_with(&format!("https://hub.example.edu/jupyter/lab?token={}", TOKEN), "ponderosa", &options);
+        assert_eq!(conn.port, 9999);
+        assert_eq!(conn.server_url(), "http://localhost:9999/jupyter");
+        assert_eq!(conn.local_url(), format!("http://localhost:9999/jupyter/lab?token={}", TOKEN));
+    }
+
+    fn ssh_host(link: &str, host: Option<&str>) -> Result<String> {
+        UrlParts::parse(&format!("{}?token={}", link, TOKEN))?.ssh_host(host)
+    }
+
+    #[test]
+    fn ssh_host_is_required_for_localhost_links() {
+        for link in ["http://localhost:8888/lab", "http://127.0.0.1:8888/lab", "http://[::1]:8888/lab",
+                     "http://0.0.0.0:8888/lab"] {
+            let err = ssh_host(link, None).unwrap_err().to_string();
+            assert!(err.contains("must be given"), "{}: {}", link, err);
+            assert_eq!(ssh_host(link, Some("ponderosa")).unwrap(), "ponderosa");
+        }
+    }
+
+    #[test]
+    fn ssh_host_is_inferred_from_the_link() {
+        assert_eq!(ssh_host("http://gpu-node-03:8888/lab", None).unwrap(), "gpu-node-03");
+        assert_eq!(ssh_host("http://gpu01.cluster.example.edu:8888/", None).unwrap(),
+                   "gpu01.cluster.example.edu");
+        assert_eq!(ssh_host("http://gpu-node-03:8888/lab", Some("gpu-node-03")).unwrap(), "gpu-node-03");
+    }
+
+    #[test]
+    fn given_ssh_host_wins_over_the_links() {
+        // a warning says which is used.
+        assert_eq!(ssh_host("http://gpu-node-03:8888/lab", Some("login01")).unwrap(), "login01");
+    }
+
+    #[test]
+    fn loopback_links_are_stored_with_localhost() {
+        let expected = format!("http://localhost:8888/lab?token={}", TOKEN);
+        for host in ["localhost", "127.0.0.1", "[::1]", "0.0.0.0"] {
+            let link = format!("http://{}:8888/lab?token={}", host, TOKEN);
+            assert_eq!(normalize_link(&link), expected, "{}", host);
+            let conn = session(&link);
+            assert_eq!(conn.link, expected, "{}", host);
+            assert_eq!(conn.local_url(), expected, "{}", host);
+        }
+        // a remote host's own name is kept.
+        let link = format!("http://gpu-node-03:8888/lab?token={}", TOKEN);
+        assert_eq!(normalize_link(&link), link);
+    }
+
+    /// Lab links with more than a token in the query, or a fragment.
+    fn lab_links() -> Vec<String> {
+        [
+            "/lab?token={t}&reset",
+            "/lab/workspaces/analysis?clone=workspace&token={t}",
+            "/lab/tree/project?file-browser-path=/data/runs&token={t}",
+            "/lab/tree/analysis.ipynb?token={t}#Results-2",
+            "/doc/tree/notes.md?token={t}&mode=single-document",
+            "/lab?token={t}&theme=JupyterLab%20Dark"
+        ].iter().map(|path| format!("http://localhost:8888{}", path.replace("{t}", TOKEN))).collect()
+    }
+
+    #[test]
+    fn query_and_fragment_round_trip() {
+        for link in lab_links() {
+            assert_eq!(session(&link).local_url(), link);
+        }
+    }
+
+    #[test]
+    fn query_and_fragment_survive_a_port_remap() {
+        let options = NewOptions { remote_port: Some(9999), ..NewOptions::default() };
+        for link in lab_links() {
+            let conn = session_with(&link, "ponderosa", &options);
+            assert_eq!(conn.local_url(), link.replace(":8888/", ":9999/"));
+        }
+    }
+
+    /// A cache holding `sessions`.
+    fn cache_of(sessions: Vec<Connection>) -> ConnectionCache {
+        ConnectionCache { connections: sessions.into_iter().map(|conn| (conn.key(), conn)).collect() }
+    }
+
+    #[test]
+    fn ipv6_links_parse() {
+        let parts = UrlParts::parse(&format!("http://[fd00::12]:8888/lab?token={}", TOKEN)).unwrap();
+        assert_eq!(parts.port, 8888);
+        assert_eq!(parts.token.as_deref(), Some(TOKEN));
+        assert_eq!(parts.remote_host.as_deref(), Some("[fd00::12]"));
+        assert_eq!(parts.ssh_host(None).unwrap(), "fd00::12");
+        assert_eq!(parts.ssh_host(Some("[fd00::12]")).unwrap(), "fd00::12");
+    }
+
+    #[test]
+    fn ipv6_keys_round_trip() {
+        assert_eq!(make_key("fd00::12", 8888), "[fd00::12]:8888");
+        assert_eq!(make_key("[fd00::12]", 8888), "[fd00::12]:8888");
+        assert_eq!(make_key("ponderosa", 8906), "ponderosa:8906");
+        for key in ["[fd00::12]:8888", "fd00::12:8888"] {
+            assert_eq!(canonical_key(key).as_deref(), Some("[fd00::12]:8888"), "{}", key);
+        }
+        assert_eq!(canonical_key("ponderosa:8906").as_deref(), Some("ponderosa:8906"));
+        assert_eq!(canonical_key("ponderosa"), None);
+
+        let conn = session_with(&format!("http://localhost:8888/lab?token={}", TOKEN), "[fd00::12]",
+                                &NewOptions::default());
+        assert_eq!(conn.host, "fd00::12");
+        assert_eq!(conn.key(), "[fd00::12]:8888");
+        let sessions = cache_of(vec![conn]);
+        for key in ["[fd00::12]:8888", "fd00::12:8888"] {
+            assert_eq!(sessions.resolve_key(key).unwrap(), "[fd00::12]:8888", "{}", key);
+        }
+    }
+
+    #[test]
+    fn ipv6_ssh_arguments() {
+        let link = format!("http://[fd00::12]:8888/lab?token={}", TOKEN);
+        let conn = session_with(&link, "fd00::12", &NewOptions::default());
+        assert_eq!(conn.ssh_args(), ["-Y", "-N", "-L", "localhost:8888:localhost:8888", "fd00::12"]);
+        let options = NewOptions { bind_address: Some("::1".to_string()), ..NewOptions::default() };
+        let conn = session_with(&link, "fd00::12", &options);
+        assert_eq!(conn.ssh_args(), ["-Y", "-N", "-L", "[::1]:8888:localhost:8888", "fd00::12"]);
+        assert_eq!(conn.local_url(), format!("http://[::1]:8888/lab?token={}", TOKEN));
+    }
+
+    #[test]
+    fn deep_paths_are_kept_with_base_url_and_token() {
+        // link path, base_url, notebook path
+        let cases = [
+            ("/jupyter/lab/tree/project/analysis.ipynb", "/jupyter/", "project/analysis.ipynb"),
+            ("/jupyter/notebooks/project/analysis.ipynb", "/jupyter/", "project/analysis.ipynb"),
+            ("/user/vince/lab/tree/runs/2024%20summary.ipynb", "/user/vince/", "runs/2024 summary.ipynb"),
+            ("/lab/tree/analysis.ipynb", "/", "analysis.ipynb")
+        ];
+        let options = NewOptions { remote_port: Some(9999), ..NewOptions::default() };
+        for (path, base_url, notebook) in cases {
+            let link = format!("http://localhost:8888{}?token={}", path, TOKEN);
+            let conn = session(&link);
+            assert_eq!(conn.base_url(), base_url, "{}", path);
+            assert_eq!(conn.local_url(), link, "{}", path);
+            assert_eq!(extract_path_label(&link).as_deref(), Some(notebook), "{}", path);
+            let remapped = session_with(&link, "ponderosa", &options);
+            assert_eq!(remapped.local_url(), link.replace(":8888/", ":9999/"), "{}", path);
+        }
+    }
+
+    #[test]
+    fn ugly_links_are_cleaned_up() {
+        let link = format!("http://localhost:8888/lab?token={}", TOKEN);
+        let (head, tail) = link.split_at(40);
+        let cases = [
+            // wrapped by the terminal, with and without indentation
+            format!("{}\n{}", head, tail),
+            format!("{}\r\n    {}", head, tail),
+            format!("  {} \n", link),
+            // quoted, or in Outlook's angle brackets
+            format!("<{}>", link),
+            format!("\"{}\"", link),
+            format!("'{}'", link),
+            format!("`{}`", link),
+            format!(" <{}\n{}> ", head, tail)
+        ];
+        for text in cases.iter() {
+            assert_eq!(clean_link(text), link, "{:?}", text);
+            assert_eq!(find_link(text, None).unwrap().link, link, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn console_output_is_left_to_find_links() {
+        let (head, tail) = TOKEN.split_at(20);
+        let output = format!("[I 2024-01-15 14:30:00.000 ServerApp] Jupyter Server is running at:\n\
+                              [I 2024-01-15 14:30:00.000 ServerApp] http://gpu-node-03:8888/lab?token={t}\n\
+                              [I 2024-01-15 14:30:00.000 ServerApp]     http://127.0.0.1:8888/lab?token={t}\n\
+                              \n    To access the server, open this file in a browser:\n\
+                              \x20       http://localhost:8888/lab?token={h}\n{tl}\n", t = TOKEN, h = head, tl = tail);
+        assert_eq!(clean_link(&output), output.trim());
+        let wrapped = format!("http://localhost:8888/lab?token={}", TOKEN);
+        assert!(find_links(&output).iter().any(|(link, _)| *link == wrapped));
+        let found = find_link(&output, None).unwrap();
+        assert_eq!(normalize_link(&found.link), wrapped);
+        assert_eq!(found.parts.remote_host.as_deref(), Some("gpu-node-03"));
+    }
+
+    #[test]
+    fn ansi_escapes_are_stripped_from_links() {
+        let link = format!("http://localhost:8888/lab?token={}", TOKEN);
+        let cases = [
+            // bold green, as from a coloured log
+            format!("\x1b[1;32m{}\x1b[0m", link),
+            // 256-colour and erase to end of line, as tmux copies them
+            format!("\x1b[38;5;39m{}\x1b[39m\x1b[K", link),
+            // underlined, as less -R shows links
+            format!("\x1b[4m{}\x1b[24m", link),
+            // OSC 8 hyperlinks, ended by ST and by BEL
+            format!("\x1b]8;;{l}\x1b\\{l}\x1b]8;;\x1b\\", l = link),
+            format!("\x1b]8;id=jupyter;{l}\x07{l}\x1b]8;;\x07", l = link),
+            // a window title set on the same line
+            format!("\x1b]0;jupyter lab\x07{}", link)
+        ];
+        for text in cases.iter() {
+            assert_eq!(strip_ansi(text), link, "{:?}", text);
+            assert_eq!(find_link(text, None).unwrap().link, link, "{:?}", text);
+        }
+        assert_eq!(strip_ansi(&link), link);
+    }
+
+    /// A cache as read from a cache file with `yaml` in it.
+    fn cache_from_yaml(yaml: &str) -> ConnectionCache {
+        let sessions: HashMap<String, Connection> = serde_yaml::from_str(yaml).unwrap();
+        cache_of(sessions.into_values().collect())
+    }
+
+    /// What `rjy list` prints for `sessions`, in the default order.
+    fn list_output(sessions: &ConnectionCache, output: OutputFormat, layout: &TableLayout) -> String {
+        let mut out = Vec::new();
+        sessions.list(&mut out, &SessionFilter::default(), &SortOrder::default(), output, None, layout).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Disconnected sessions, with characters porcelain output has to escape.
+    const PORCELAIN_FIXTURE: &str = r#"
+ponderosa:8906:
+  host: ponderosa
+  port: 8906
+  link: http://localhost:8888/lab?token=abc123
+  pid: null
+  token: abc123
+  tags: [gpu, projA]
+  note: "GPU run\t#2\nsecond line \\ backslash"
+"[fd00::12]:8888":
+  host: fd00::12
+  port: 8888
+  link: http://localhost:8888/jupyter/lab?token=def456
+  pid: null
+  token: def456
+  base_url: /jupyter/
+"odd\thost:9000":
+  host: "odd\thost"
+  port: 9000
+  link: http://localhost:9000/?token=ghi789
+  pid: null
+  token: ghi789
+  note: "C:\\Users\\me\r"
+"#;
+
+    #[test]
+    fn porcelain_v1_output_is_exact() {
+        let sessions = cache_from_yaml(PORCELAIN_FIXTURE);
+        let layout = TableLayout { fields: Vec::new(), width: None, colour: true, hyperlinks: true };
+        let out = list_output(&sessions, OutputFormat::Porcelain(PorcelainVersion::V1), &layout);
+        let expected = concat!(
+            "[fd00::12]:8888\tdisconnected\t\tfd00::12\t8888\t8888\t",
+            "http://localhost:8888/jupyter/lab?token=def456\t\t\n",
+            "odd\\thost:9000\tdisconnected\t\todd\\thost\t9000\t9000\t",
+            "http://localhost:9000/?token=ghi789\t\tC:\\\\Users\\\\me\\r\n",
+            "ponderosa:8906\tdisconnected\t\tponderosa\t8888\t8906\t",
+            "http://localhost:8906/lab?token=abc123\tgpu,projA\tGPU run\\t#2\\nsecond line \\\\ backslash\n");
+        assert_eq!(out.as_bytes(), expected.as_bytes());
+    }
+
+    /// A stand-in for a session's ssh tunnel: a process named ssh that runs
+    /// until dropped.
+    struct FakeTunnel {
+        child: std::process::Child,
+        _dir: tempfile::TempDir
+    }
+
+    impl FakeTunnel {
+        fn start() -> FakeTunnel {
+            let dir = tempfile::tempdir().unwrap();
+            let ssh = dir.path().join("ssh");
+            fs::write(&ssh, "#!/bin/sh\ntrap 'kill $!; exit 0' TERM\nsleep 60 &\nwait\n").unwrap();
+            set_permissions(&ssh, Permissions::from_mode(0o755)).unwrap();
+            // another test's fork can briefly hold the script open for writing.
+            let child = loop {
+                match Command::new(&ssh).stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
+                    Err(err) if err.kind() == io::ErrorKind::ExecutableFileBusy => thread::sleep(Duration::from_millis(10)),
+                    spawned => break spawned.unwrap()
+                }
+            };
+            // spawn can return before the child has taken the script's name.
+            let comm = format!("/proc/{}/comm", child.id());
+            while fs::read_to_string(&comm).map_or(true, |name| name.trim() != "ssh") {
+                thread::sleep(Duration::from_millis(1));
+            }
+            FakeTunnel { child, _dir: dir }
+        }
+
+        fn pid(&self) -> Option<u32> {
+            Some(self.child.id())
+        }
+    }
+
+    impl Drop for FakeTunnel {
+        fn drop(&mut self) {
+            let _ = kill(Pid::from_raw(self.child.id() as i32), Signal::SIGTERM);
+            let _ = self.child.wait();
+        }
+    }
+
+    const SORT_FIXTURE: &str = r#"
+alder:8890:
+  host: alder
+  port: 8890
+  link: http://localhost:8890/lab?token=abc123
+  pid: null
+  created: 2026-03-02T10:00:00Z
+  last_used: 2026-03-05T09:00:00Z
+birch:8888:
+  host: birch
+  port: 8888
+  link: http://localhost:8888/lab?token=def456
+  pid: null
+  created: 2026-03-01T10:00:00Z
+birch:9001:
+  host: birch
+  port: 9001
+  link: http://localhost:9001/lab?token=ghi789
+  pid: null
+  last_used: 2026-03-04T09:00:00Z
+cedar:8889:
+  host: cedar
+  port: 8889
+  link: http://localhost:8889/lab?token=jkl012
+  pid: null
+  created: 2026-03-03T10:00:00Z
+  last_used: 2026-03-06T09:00:00Z
+"#;
+
+    fn sorted_keys(sessions: &ConnectionCache, sort: SortKey, reverse: bool) -> Vec<String> {
+        let mut keys: Vec<String> = sessions.connections.keys().cloned().collect();
+        sessions.sort_keys(&mut keys, &SortOrder { sort, reverse });
+        keys
+    }
+
+    #[test]
+    fn sessions_sort_by_each_key() {
+        let mut sessions = cache_from_yaml(SORT_FIXTURE);
+        let tunnel = FakeTunnel::start();
+        sessions.connections.get_mut("cedar:8889").unwrap().pid = tunnel.pid();
+        let cases = [
+            (SortKey::Host, ["alder:8890", "birch:8888", "birch:9001", "cedar:8889"]),
+            (SortKey::Port, ["birch:8888", "cedar:8889", "alder:8890", "birch:9001"]),
+            // the rest go by host, then port.
+            (SortKey::Status, ["cedar:8889", "alder:8890", "birch:8888", "birch:9001"]),
+            // sessions without a timestamp go last.
+            (SortKey::Created, ["birch:8888", "alder:8890", "cedar:8889", "birch:9001"]),
+            (SortKey::LastUsed, ["birch:9001", "alder:8890", "cedar:8889", "birch:8888"])
+        ];
+        for (sort, expected) in cases {
+            assert_eq!(sorted_keys(&sessions, sort, false), expected, "{:?}", sort);
+            let reversed: Vec<&str> = expected.iter().rev().copied().collect();
+            assert_eq!(sorted_keys(&sessions, sort, true), reversed, "{:?} reversed", sort);
+        }
+    }
+
+    mod parse_properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Tokens with the characters a query string treats specially.
+        fn token() -> impl Strategy<Value = String> {
+            prop::collection::vec(prop_oneof!["[A-Za-z0-9._~-]{1,8}", "[+=& #é]", Just("%20".to_string())], 1..8)
+                .prop_map(|pieces| pieces.concat())
+        }
+
+        fn host() -> impl Strategy<Value = &'static str> {
+            prop::sample::select(vec!["localhost", "127.0.0.1", "[::1]", "gpu-node-03", "[fd00::12]"])
+        }
+
+        fn path() -> impl Strategy<Value = &'static str> {
+            prop::sample::select(vec!["/", "/lab", "/tree", "/jupyter/lab", "/lab/tree/analysis.ipynb"])
+        }
+
+        proptest! {
+            #[test]
+            fn parse_never_panics(link in ".*") {
+                let _ = UrlParts::parse(&link);
+            }
+
+            #[test]
+            fn parse_never_panics_on_link_like_text(link in "(https?|ftp)://[a-z0-9.:\\[\\]@-]{0,24}(/[a-z0-9%?=&#+ -]{0,24})?") {
+                let _ = UrlParts::parse(&link);
+            }
+
+            #[test]
+            fn port_and_token_round_trip(host in host(), port in 1u16.., path in path(), token in token(),
+                                         reset in any::<bool>()) {
+                let extra = if reset { "&reset" } else { "" };
+                let link = format!("http://{}:{}{}?token={}{}", host, port, path, encode_token(&token), extra);
+                let parts = UrlParts::parse(&link).unwrap();
+                prop_assert_eq!(parts.port, port);
+                prop_assert_eq!(parts.token.as_deref(), Some(token.as_str()));
+                let parts = UrlParts::parse(&raw_link("https", port, &token).unwrap()).unwrap();
+                prop_assert_eq!(parts.port, port);
+                prop_assert_eq!(parts.token, Some(token));
+            }
+
+            #[test]
+            fn malformed_ports_are_errors(host in host(), port in prop_oneof![(65536u32..).prop_map(|port| port.to_string()),
+                                                           "[0-9]{0,3}[a-z_][0-9a-z]{0,3}", "-[0-9]{1,5}"],
+                                          token in token()) {
+                let link = format!("http://{}:{}/lab?token={}", host, port, encode_token(&token));
+                let err = parse_error(&link);
+                prop_assert!(err.contains("Incorrect Jupyter link format"), "{}", err);
+            }
+
+            #[test]
+            fn missing_tokens_are_errors(host in host(), port in 1u16.., path in path()) {
+                let parts = UrlParts::parse(&format!("http://{}:{}{}", host, port, path)).unwrap();
+                prop_assert_eq!(parts.token, None);
+                let parts = UrlParts::parse(&format!("http://{}:{}{}?token=&reset", host, port, path)).unwrap();
+                prop_assert_eq!(parts.token, None);
+                let err = link_from_parts(Some(host), Some(port), None, false).unwrap_err().to_string();
+                prop_assert!(err.contains("No token given"), "{}", err);
+            }
+        }
+    }
+
+    /// `rjy list`'s table without colour, leaving out the columns that
+    /// change from run to run (pid and age).
+    fn list_table(sessions: &ConnectionCache) -> String {
+        let fields = ["name", "key", "status", "auto", "host", "port", "local-port", "link", "tags", "note"];
+        let layout = TableLayout { fields: fields.iter().map(|field| field.to_string()).collect(),
+                                   width: None, colour: false, hyperlinks: false };
+        list_output(sessions, OutputFormat::TableNoColor, &layout)
+    }
+
+    const LIST_FIXTURE: &str = r#"
+alder:8890:
+  host: alder
+  port: 8890
+  link: http://localhost:8890/lab?token=abc123
+  pid: null
+  token: abc123
+  alias: analysis
+  tags: [gpu, projA]
+birch:8888:
+  host: birch
+  port: 8888
+  link: http://localhost:8888/jupyter/lab?token=def456
+  pid: null
+  token: def456
+  base_url: /jupyter/
+  note: left running over the weekend
+  auto: false
+cedar:8889:
+  host: cedar
+  port: 8889
+  link: http://localhost:8889/lab?token=ghi789
+  pid: null
+  token: ghi789
+  auto_kill_at: 2026-03-01T10:00:00Z
+dogwood:9001:
+  host: dogwood
+  port: 9001
+  link: http://localhost:8888/lab
+  pid: null
+  pinned: true
+  persistent: true
+"#;
+
+    #[test]
+    fn list_of_no_sessions() {
+        insta::assert_snapshot!(list_table(&ConnectionCache::default()));
+    }
+
+    #[test]
+    fn list_of_one_connected_session() {
+        let mut sessions = cache_from_yaml(LIST_FIXTURE);
+        sessions.connections.retain(|key, _| key == "alder:8890");
+        let tunnel = FakeTunnel::start();
+        sessions.connections.get_mut("alder:8890").unwrap().pid = tunnel.pid();
+        insta::assert_snapshot!(list_table(&sessions));
+    }
+
+    #[test]
+    fn list_of_mixed_statuses() {
+        let mut sessions = cache_from_yaml(LIST_FIXTURE);
+        let (alder, cedar) = (FakeTunnel::start(), FakeTunnel::start());
+        sessions.connections.get_mut("alder:8890").unwrap().pid = alder.pid();
+        // past its --timeout-kill, so disconnected though its tunnel runs.
+        sessions.connections.get_mut("cedar:8889").unwrap().pid = cedar.pid();
+        insta::assert_snapshot!(list_table(&sessions));
+    }
+
+    const LOOKUP_FIXTURE: &str = r#"
+gpu-node-01:8888:
+  host: gpu-node-01
+  port: 8888
+  link: http://localhost:8888/lab?token=abc123
+  pid: null
+  alias: analysis
+gpu-node-01:8890:
+  host: gpu-node-01
+  port: 8890
+  link: http://localhost:8890/lab?token=def456
+  pid: null
+gpu-node-02:8888:
+  host: gpu-node-02
+  port: 8888
+  link: http://localhost:8888/lab?token=ghi789
+  pid: null
+  alias: anneal
+login01:9000:
+  host: login01
+  port: 9000
+  link: http://localhost:9000/lab?token=jkl012
+  pid: null
+"#;
+
+    #[test]
+    fn unique_prefixes_resolve() {
+        let sessions = cache_from_yaml(LOOKUP_FIXTURE);
+        // a key and a name, then prefixes of a key and names, a host, and part of a key.
+        let cases = [
+            ("gpu-node-01:8890", "gpu-node-01:8890"),
+            ("analysis", "gpu-node-01:8888"),
+            ("login", "login01:9000"),
+            ("anal", "gpu-node-01:8888"),
+            ("ann", "gpu-node-02:8888"),
+            ("gpu-node-02", "gpu-node-02:8888"),
+            ("02:", "gpu-node-02:8888")
+        ];
+        for (s, key) in cases {
+            assert_eq!(sessions.resolve_key(s).unwrap(), key, "{}", s);
+        }
+    }
+
+    #[test]
+    fn ambiguous_prefixes_list_the_candidates() {
+        let sessions = cache_from_yaml(LOOKUP_FIXTURE);
+        let cases: [(&str, &[&str]); 4] = [
+            ("gpu-node-01", &["gpu-node-01:8888", "gpu-node-01:8890"]),
+            ("gpu", &["gpu-node-01:8888", "gpu-node-01:8890", "gpu-node-02:8888"]),
+            ("an", &["gpu-node-01:8888", "gpu-node-02:8888"]),
+            (":8888", &["gpu-node-01:8888", "gpu-node-02:8888"])
+        ];
+        for (s, keys) in cases {
+            let err = sessions.resolve_key(s).unwrap_err().to_string();
+            let expected = format!("'{}' matches more than one session:\n  {}\n", s, keys.join("\n  "));
+            assert!(err.starts_with(&expected), "{}: {}", s, err);
+        }
+    }
+
+    #[test]
+    fn unmatched_keys_suggest_a_close_one() {
+        let sessions = cache_from_yaml(LOOKUP_FIXTURE);
+        let err = sessions.resolve_key("logni01").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'login01'?"), "{}", err);
+        let err = sessions.resolve_key("anaylsis").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'analysis'?"), "{}", err);
+        let err = sessions.resolve_key("ponderosa").unwrap_err().to_string();
+        assert_eq!(err, "Could not find a remote Jupyter session with key or name 'ponderosa'.");
+    }
+
+    #[test]
+    fn auto_labels_give_the_host_and_time() {
+        let label = auto_label("hpc01.example.com");
+        let (host, time) = label.split_once(" @ ").unwrap();
+        assert_eq!(host, "hpc01.example.com");
+        assert!(chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").is_ok(), "{}", label);
+    }
+
+    #[test]
+    fn picks_are_numbers_and_ranges() {
+        assert_eq!(parse_picks("1 3\n", 4).unwrap(), vec![1, 3]);
+        assert_eq!(parse_picks("2-4", 4).unwrap(), vec![2, 3, 4]);
+        assert_eq!(parse_picks("3, 1-3", 4).unwrap(), vec![3, 1, 2]);
+        for answer in ["0", "5", "3-2", "2-5", "x", "1-"] {
+            assert!(parse_picks(answer, 4).is_err(), "{}", answer);
+        }
+        assert_eq!(parse_picks("\n", 4).unwrap_err().to_string(), "No session chosen.");
+    }
+}
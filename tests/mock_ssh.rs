@@ -0,0 +1,148 @@
+//! Sessions whose tunnels are run by a mock ssh: a script that stands in for
+//! ssh through the ssh_binary config setting, so no remote host is needed.
+
+use remote_jupyter::{ConnectionCache, NewOptions};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The mock ssh. For the host "unreachable" it fails as ssh does when it
+/// can't connect; for a remote command it answers as the server's curl would
+/// (no server on the host "noserver"); otherwise it holds the tunnel open
+/// until killed, noting a SIGTERM in term.<pid>.
+const MOCK_SSH: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+echo "$@" >> "$dir/calls"
+for arg in "$@"; do
+    case $arg in
+        unreachable) echo "ssh: Could not resolve hostname unreachable" >&2; exit 255 ;;
+        curl*) case $* in *noserver*) printf 000 ;; *) printf 200 ;; esac; exit 0 ;;
+    esac
+done
+trap 'echo TERM > "$dir/term.$$"; kill $! 2>/dev/null; exit 0' TERM
+sleep 300 &
+wait
+"#;
+
+/// The directory with the mock ssh, which HOME and the XDG directories point
+/// into. The ssh program is looked up once per process, so every test
+/// shares it.
+fn mock_dir() -> &'static Path {
+    static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let ssh = bin.join("ssh");
+        fs::write(&ssh, MOCK_SSH).unwrap();
+        fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = dir.path().join("config/rjy");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("config.toml"), format!("ssh_binary = {:?}\n", ssh)).unwrap();
+        std::env::set_var("HOME", dir.path());
+        std::env::set_var("XDG_CONFIG_HOME", dir.path().join("config"));
+        std::env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+        dir
+    }).path()
+}
+
+fn link(port: u16) -> String {
+    format!("http://localhost:{}/lab?token=0123456789abcdef0123456789abcdef0123456789abcdef", port)
+}
+
+/// Wait up to five seconds for `done`, e.g. for ssh to have started: spawn
+/// can return before the new process has taken ssh's name.
+fn eventually(done: impl Fn() -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn term_file(pid: u32) -> PathBuf {
+    mock_dir().join("bin").join(format!("term.{}", pid))
+}
+
+#[test]
+fn new_sessions_run_a_live_tunnel_until_disconnected() {
+    mock_dir();
+    let mut sessions = ConnectionCache::new();
+    let key = sessions.new_connection(&link(18801), "gpu-node-03", &NewOptions::default()).unwrap();
+    assert_eq!(key, "gpu-node-03:18801");
+    let conn = sessions.get_connection(&key).unwrap();
+    let pid = conn.pid.unwrap();
+    assert!(eventually(|| conn.is_alive()));
+    assert_eq!(conn.get_pid(), Some(pid));
+    let calls = || fs::read_to_string(mock_dir().join("bin/calls")).unwrap_or_default();
+    assert!(eventually(|| calls().contains("-Y -N -L localhost:18801:localhost:18801 gpu-node-03")), "{}", calls());
+
+    sessions.disconnect(&key).unwrap();
+    assert!(eventually(|| term_file(pid).exists()), "ssh (pid {}) got no SIGTERM", pid);
+    let conn = sessions.get_connection(&key).unwrap();
+    assert_eq!(conn.pid, None);
+    assert!(!conn.is_alive());
+}
+
+#[test]
+fn killed_tunnels_are_not_alive() {
+    mock_dir();
+    let mut sessions = ConnectionCache::new();
+    let key = sessions.new_connection(&link(18802), "gpu-node-04", &NewOptions::default()).unwrap();
+    let pid = sessions.get_connection(&key).unwrap().pid.unwrap();
+    assert!(eventually(|| sessions.get_connection(&key).unwrap().is_alive()));
+    // the tunnel dies on its own, e.g. when the network drops.
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+    assert!(eventually(|| !sessions.get_connection(&key).unwrap().is_alive()));
+    // disconnecting it then has nothing to kill.
+    sessions.disconnect(&key).unwrap();
+    assert_eq!(sessions.get_connection(&key).unwrap().pid, None);
+}
+
+#[test]
+fn tunnels_that_fail_to_connect_are_not_alive() {
+    mock_dir();
+    let mut sessions = ConnectionCache::new();
+    let key = sessions.new_connection(&link(18803), "unreachable", &NewOptions::default()).unwrap();
+    let conn = sessions.get_connection(&key).unwrap();
+    assert!(conn.pid.is_some());
+    assert!(eventually(|| !conn.is_alive()));
+    assert_eq!(conn.get_pid(), None);
+}
+
+#[test]
+fn checked_sessions_need_a_reachable_server() {
+    mock_dir();
+    let options = NewOptions { check_remote: true, ..NewOptions::default() };
+    let mut sessions = ConnectionCache::new();
+
+    let err = sessions.new_connection(&link(18804), "unreachable", &options).unwrap_err().to_string();
+    assert!(err.contains("ssh to unreachable failed"), "{}", err);
+    let err = sessions.new_connection(&link(18805), "noserver", &options).unwrap_err().to_string();
+    assert!(err.contains("No Jupyter server answered"), "{}", err);
+    assert!(sessions.get_connection("unreachable:18804").is_err());
+    assert!(sessions.get_connection("noserver:18805").is_err());
+
+    let key = sessions.new_connection(&link(18806), "gpu-node-05", &options).unwrap();
+    assert!(eventually(|| sessions.get_connection(&key).unwrap().is_alive()));
+    sessions.disconnect(&key).unwrap();
+}
+
+#[test]
+fn sessions_are_not_registered_twice() {
+    mock_dir();
+    let mut sessions = ConnectionCache::new();
+    let key = sessions.new_connection(&link(18807), "gpu-node-06", &NewOptions::default()).unwrap();
+    let pid = sessions.get_connection(&key).unwrap().pid;
+    assert!(eventually(|| sessions.get_connection(&key).unwrap().is_alive()));
+    let err = sessions.new_connection(&link(18807), "gpu-node-06", &NewOptions::default()).unwrap_err().to_string();
+    assert!(err.contains("already registered"), "{}", err);
+    assert_eq!(sessions.get_connection(&key).unwrap().pid, pid);
+    sessions.disconnect(&key).unwrap();
+}